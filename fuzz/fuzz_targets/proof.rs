@@ -0,0 +1,17 @@
+#![no_main]
+
+use halo2_mpt_circuits::{serde::SMTTrace, types::Proof, MPTProofType};
+use libfuzzer_sys::fuzz_target;
+use strum::IntoEnumIterator;
+
+// Converts any trace that deserializes with every proof type. Invalid traces and traces that
+// aren't updates of the proof type are rejected with an error, so every crash found here is an
+// input that Proof::try_from_trace panics on instead of rejecting.
+fuzz_target!(|data: &[u8]| {
+    let Ok(trace) = serde_json::from_slice::<SMTTrace>(data) else {
+        return;
+    };
+    for proof_type in MPTProofType::iter() {
+        let _ = Proof::try_from_trace(proof_type, trace.clone());
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use halo2_mpt_circuits::serde::SMTTrace;
+use libfuzzer_sys::fuzz_target;
+
+// Deserializing a trace must fail with an error instead of panicking, whatever the input.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(trace) = serde_json::from_slice::<SMTTrace>(data) {
+        let json = serde_json::to_string(&trace).unwrap();
+        let _: SMTTrace = serde_json::from_str(&json).unwrap();
+    }
+});
@@ -0,0 +1,72 @@
+//! Prints what the mpt circuit derives from traces, for sanity checking trace producers:
+//!
+//! ```text
+//! mpt-circuit inspect <trace.json> [proof type]
+//! ```
+//!
+//! The file is either a list of (proof type, trace) pairs, or a single trace, in which case the
+//! proof type has to be given, e.g. `NonceChanged`.
+//!
+//! Traces that are invalid or aren't updates of their proof type are reported on stderr, with exit
+//! status 2, instead of panicking.
+use halo2_mpt_circuits::{
+    gadgets::mpt_update::MptUpdateConfig, serde::SMTTrace, types::Proof, MPTProofType,
+};
+use halo2_proofs::circuit::Value;
+use strum::IntoEnumIterator;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 || args[1] != "inspect" {
+        exit_with_error("usage: mpt-circuit inspect <trace.json> [proof type]");
+    }
+
+    let json = std::fs::read_to_string(&args[2])
+        .unwrap_or_else(|e| exit_with_error(&format!("cannot read {}: {e}", args[2])));
+    let traces: Vec<(MPTProofType, SMTTrace)> = match args.get(3) {
+        Some(name) => {
+            let proof_type = MPTProofType::iter()
+                .find(|proof_type| <&str>::from(proof_type) == name)
+                .unwrap_or_else(|| exit_with_error(&format!("unknown proof type {name}")));
+            let trace = serde_json::from_str(&json)
+                .unwrap_or_else(|e| exit_with_error(&format!("cannot parse trace: {e}")));
+            vec![(proof_type, trace)]
+        }
+        None => serde_json::from_str(&json).unwrap_or_else(|e| {
+            exit_with_error(&format!("cannot parse (proof type, trace) pairs: {e}"))
+        }),
+    };
+
+    let proofs: Vec<Proof> = traces
+        .into_iter()
+        .enumerate()
+        .map(|(i, (proof_type, trace))| {
+            Proof::try_from_trace(proof_type, trace)
+                .unwrap_or_else(|e| exit_with_error(&format!("proof {i}: {e}")))
+        })
+        .collect();
+    let csv = MptUpdateConfig::layout_csv(&proofs, None, Value::unknown())
+        .unwrap_or_else(|e| exit_with_error(&format!("cannot assign proofs: {e}")));
+    let mut lines = csv.lines();
+    let header = lines.next().unwrap();
+    for (i, proof) in proofs.iter().enumerate() {
+        let [old_type, new_type] = proof.account_path_types();
+        println!("proof {i}");
+        println!("  claim: {:?}", proof.claim);
+        println!("  account key: {:?}", proof.account_key);
+        println!("  account path types: old type {old_type}, new type {new_type}");
+        println!("  old account: {:?}", proof.old_account);
+        println!("  new account: {:?}", proof.new_account);
+        println!("  storage: {:?}", proof.storage);
+        println!("  rows:");
+        println!("    {header}");
+        for line in lines.by_ref().take(proof.n_rows()) {
+            println!("    {line}");
+        }
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(2);
+}
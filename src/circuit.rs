@@ -1,6 +1,6 @@
 use crate::{
-    gadgets::poseidon::PoseidonTable, hash_traces, serde::SMTTrace, types::Proof, MPTProofType,
-    MptCircuitConfig,
+    gadgets::poseidon::PoseidonTable, hash_traces, profile::profile, serde::SMTTrace, types::Proof,
+    MPTProofType, MptCircuitConfig,
 };
 use halo2_proofs::{
     circuit::{Layouter, SimpleFloorPlanner},
@@ -8,6 +8,8 @@ use halo2_proofs::{
     plonk::{Circuit, ConstraintSystem, Error, FirstPhase},
 };
 
+/// The mpt circuit together with the poseidon table that its hashes are looked up in, for
+/// mock proving mpt updates. `n_rows` has to be at least `MptCircuitConfig::n_rows_required`.
 #[derive(Clone, Debug, Default)]
 pub struct TestCircuit {
     n_rows: usize,
@@ -21,6 +23,10 @@ impl TestCircuit {
             proofs: traces.into_iter().map(Proof::from).collect(),
         }
     }
+
+    pub fn from_proofs(n_rows: usize, proofs: Vec<Proof>) -> Self {
+        Self { n_rows, proofs }
+    }
 }
 
 impl Circuit<Fr> for TestCircuit {
@@ -48,7 +54,10 @@ impl Circuit<Fr> for TestCircuit {
         layouter.assign_region(
             || "load poseidon table",
             |mut region| {
-                poseidon.load(&mut region, &hash_traces(&self.proofs));
+                let traces = profile("poseidon hash traces", || hash_traces(&self.proofs));
+                profile("poseidon table assignment", || {
+                    poseidon.load(&mut region, &traces)
+                })?;
                 Ok(())
             },
         )
@@ -1,9 +1,12 @@
+#[cfg(not(feature = "poseidon-codehash"))]
+use crate::gadgets::keccak::KeccakLookup;
 use crate::gadgets::poseidon::PoseidonLookup;
 use halo2_proofs::{
     halo2curves::ff::FromUniformBytes,
     plonk::{ConstraintSystem, SecondPhase},
 };
 use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
 
 mod binary_column;
 mod binary_query;
@@ -12,15 +15,33 @@ mod query;
 
 pub use binary_column::BinaryColumn;
 pub use binary_query::BinaryQuery;
+pub(crate) use column::cell_value;
 pub use column::{AdviceColumn, FixedColumn, SecondPhaseAdviceColumn, SelectorColumn};
 pub use query::Query;
 
 pub struct ConstraintBuilder<F: FromUniformBytes<64> + Ord> {
-    constraints: Vec<(&'static str, Query<F>)>,
+    // (gate name, constraint name, constraint)
+    constraints: Vec<(&'static str, &'static str, Query<F>)>,
+    // (name, exclusive branch, lookup)
     #[allow(clippy::type_complexity)]
-    lookups: Vec<(&'static str, Vec<(Query<F>, Query<F>)>)>,
+    lookups: Vec<(
+        &'static str,
+        Option<(&'static str, usize)>,
+        Vec<(Query<F>, Query<F>)>,
+    )>,
 
     conditions: Vec<BinaryQuery<F>>,
+    current_gate: Option<&'static str>,
+    current_branch: Option<(&'static str, usize)>,
+    n_branches: usize,
+
+    // (gate name or "lookups", constraint or lookup name, conditions after every_row)
+    #[allow(clippy::type_complexity)]
+    spec: Vec<(&'static str, &'static str, Vec<BinaryQuery<F>>)>,
+
+    // (constraint or lookup name, condition)
+    #[cfg(feature = "coverage")]
+    coverage_probes: Vec<(String, BinaryQuery<F>)>,
 }
 
 impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
@@ -30,9 +51,24 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
             lookups: vec![],
 
             conditions: vec![every_row.current()],
+            current_gate: None,
+            current_branch: None,
+            n_branches: 0,
+
+            spec: vec![],
+
+            #[cfg(feature = "coverage")]
+            coverage_probes: vec![],
         }
     }
 
+    /// The name and condition of every constraint and lookup added so far. A witness exercises
+    /// a constraint or lookup on the rows where its condition is true.
+    #[cfg(feature = "coverage")]
+    pub fn coverage_probes(&self) -> Vec<(String, BinaryQuery<F>)> {
+        self.coverage_probes.clone()
+    }
+
     pub fn every_row_selector(&self) -> BinaryQuery<F> {
         self.conditions
             .first()
@@ -41,11 +77,63 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
     }
 
     pub fn assert_zero(&mut self, name: &'static str, query: Query<F>) {
+        self.add_to_spec(self.current_gate.unwrap_or(name), name);
         let condition = self
             .conditions
             .iter()
             .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
-        self.constraints.push((name, condition.condition(query)))
+        #[cfg(feature = "coverage")]
+        self.coverage_probes.push((
+            format!("{}: {}", self.current_gate.unwrap_or(name), name),
+            condition.clone(),
+        ));
+        self.constraints.push((
+            self.current_gate.unwrap_or(name),
+            name,
+            condition.condition(query),
+        ))
+    }
+
+    fn add_to_spec(&mut self, group: &'static str, name: &'static str) {
+        self.spec.push((group, name, self.conditions[1..].to_vec()));
+    }
+
+    /// Returns a markdown list of the gates and lookups added so far. The constraints of each
+    /// gate and the lookups are nested under the conditions they apply to, in the order in
+    /// which they were added.
+    pub fn spec(&self) -> String {
+        let mut groups: Vec<&'static str> = vec![];
+        for (group, _, _) in &self.spec {
+            if !groups.contains(group) {
+                groups.push(group);
+            }
+        }
+        // Lookups go last, after all the gates.
+        if let Some(i) = groups.iter().position(|group| *group == "lookups") {
+            let lookups = groups.remove(i);
+            groups.push(lookups);
+        }
+
+        let mut spec = String::new();
+        for group in groups {
+            spec.push_str(&format!("## {group}\n\n"));
+            let mut previous_conditions: Vec<String> = vec![];
+            for (_, name, conditions) in self.spec.iter().filter(|(g, _, _)| *g == group) {
+                let conditions: Vec<_> = conditions.iter().map(|c| c.0.to_string()).collect();
+                let n_shared = previous_conditions
+                    .iter()
+                    .zip(&conditions)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                for (depth, condition) in conditions.iter().enumerate().skip(n_shared) {
+                    spec.push_str(&format!("{}- if `{condition}`\n", "  ".repeat(depth)));
+                }
+                spec.push_str(&format!("{}- {name}\n", "  ".repeat(conditions.len())));
+                previous_conditions = conditions;
+            }
+            spec.push('\n');
+        }
+        spec
     }
 
     pub fn assert_equal(&mut self, name: &'static str, left: Query<F>, right: Query<F>) {
@@ -66,16 +154,43 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
         self.conditions.pop().unwrap();
     }
 
+    /// Constraints added in `configure` are put into a single gate called `name`, instead of
+    /// one gate per constraint name.
+    pub fn gate(&mut self, name: &'static str, configure: impl FnOnce(&mut Self)) {
+        let outer_gate = self.current_gate.replace(name);
+        configure(self);
+        self.current_gate = outer_gate;
+    }
+
+    /// Every call with the same `group` is a branch of that group, and lookups into the same
+    /// table from different branches of a group are combined into one lookup argument. This is
+    /// only sound if all conditions of the lookups added in different branches are mutually
+    /// exclusive, e.g. because each branch is conditioned on a different variant of a OneHot.
+    pub fn exclusive_branch(&mut self, group: &'static str, configure: impl FnOnce(&mut Self)) {
+        assert!(
+            self.current_branch.is_none(),
+            "Cannot nest exclusive branches"
+        );
+        self.current_branch = Some((group, self.n_branches));
+        self.n_branches += 1;
+        configure(self);
+        self.current_branch = None;
+    }
+
     pub fn add_lookup<const N: usize>(
         &mut self,
         name: &'static str,
         left: [Query<F>; N],
         right: [Query<F>; N],
     ) {
+        self.add_to_spec("lookups", name);
         let condition = self
             .conditions
             .iter()
             .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
+        #[cfg(feature = "coverage")]
+        self.coverage_probes
+            .push((format!("lookup: {name}"), condition.clone()));
         let mut lookup: Vec<_> = left
             .into_iter()
             .map(|q| q * condition.clone())
@@ -83,7 +198,7 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
             .collect();
         // If condition is true, every_row_selector must be enabled.
         lookup.push((condition.into(), self.every_row_selector().into()));
-        self.lookups.push((name, lookup))
+        self.lookups.push((name, self.current_branch, lookup))
     }
 
     pub fn poseidon_lookup(
@@ -92,11 +207,20 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
         [left, right, domain, hash]: [Query<F>; 4],
         poseidon: &impl PoseidonLookup,
     ) {
+        if cfg!(feature = "skip-poseidon-lookups") {
+            return;
+        }
+        self.add_to_spec("lookups", name);
         let condition = self
             .conditions
             .iter()
             .skip(1) // Save a degree by skipping every row selector
             .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
+        #[cfg(feature = "coverage")]
+        self.coverage_probes.push((
+            format!("lookup: {name}"),
+            condition.clone().and(self.every_row_selector()),
+        ));
         let extended_queries = [
             Query::one(),
             hash,
@@ -122,6 +246,7 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
 
         self.lookups.push((
             name,
+            self.current_branch,
             extended_queries
                 .into_iter()
                 .zip_eq(poseidon_lookup_queries)
@@ -129,6 +254,40 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
         ))
     }
 
+    #[cfg(not(feature = "poseidon-codehash"))]
+    pub fn keccak_lookup(
+        &mut self,
+        name: &'static str,
+        output_rlc: Query<F>,
+        keccak: &dyn KeccakLookup,
+    ) {
+        self.add_to_spec("lookups", name);
+        let condition = self
+            .conditions
+            .iter()
+            .skip(1) // Save a degree by skipping every row selector
+            .fold(BinaryQuery::one(), |a, b| a.and(b.clone()));
+        #[cfg(feature = "coverage")]
+        self.coverage_probes.push((
+            format!("lookup: {name}"),
+            condition.clone().and(self.every_row_selector()),
+        ));
+        let extended_queries =
+            [Query::one(), Query::one(), output_rlc].map(|q| q * condition.clone());
+
+        let (q_enable, is_final, output_rlc) = keccak.lookup_columns();
+        let keccak_lookup_queries = [q_enable.current(), is_final.current(), output_rlc.current()];
+
+        self.lookups.push((
+            name,
+            self.current_branch,
+            extended_queries
+                .into_iter()
+                .zip_eq(keccak_lookup_queries)
+                .collect(),
+        ))
+    }
+
     pub fn build_columns<const A: usize, const B: usize, const C: usize>(
         &self,
         cs: &mut ConstraintSystem<F>,
@@ -167,10 +326,57 @@ impl<F: FromUniformBytes<64> + Ord> ConstraintBuilder<F> {
             "Cannot call build while in a condition"
         );
 
-        for (name, query) in self.constraints {
-            cs.create_gate(name, |meta| vec![query.run(meta)])
+        // Constraints with the same gate name are combined into one gate, in the order in which
+        // the gate names first appear.
+        let mut gate_indices = HashMap::new();
+        let mut gates: Vec<(&'static str, Vec<(&'static str, Query<F>)>)> = vec![];
+        for (gate_name, name, query) in self.constraints {
+            let index = *gate_indices.entry(gate_name).or_insert_with(|| {
+                gates.push((gate_name, vec![]));
+                gates.len() - 1
+            });
+            gates[index].1.push((name, query));
+        }
+        for (gate_name, constraints) in gates {
+            cs.create_gate(gate_name, |meta| {
+                constraints
+                    .into_iter()
+                    .map(|(name, query)| (name, query.run(meta)))
+                    .collect::<Vec<_>>()
+            })
+        }
+        // The inputs of lookups into the same table from different branches of the same group
+        // are added together. Because at most one of their conditions is true on any row, the
+        // sum is equal to the input of the lookup whose condition is true, or 0 if there is none.
+        #[allow(clippy::type_complexity)]
+        let mut lookups: Vec<(&'static str, Vec<(Query<F>, Query<F>)>)> = vec![];
+        let mut multiplexed_lookups: Vec<(&'static str, HashSet<usize>, usize)> = vec![];
+        for (name, branch, lookup) in self.lookups {
+            let Some((group, branch)) = branch else {
+                lookups.push((name, lookup));
+                continue;
+            };
+            let multiplexed_lookup = multiplexed_lookups.iter_mut().find(|(g, branches, i)| {
+                *g == group
+                    && !branches.contains(&branch)
+                    && lookups[*i].1.len() == lookup.len()
+                    && lookups[*i]
+                        .1
+                        .iter()
+                        .zip(&lookup)
+                        .all(|((_, table), (_, other_table))| table == other_table)
+            });
+            if let Some((_, branches, i)) = multiplexed_lookup {
+                branches.insert(branch);
+                for ((input, _), (other_input, _)) in lookups[*i].1.iter_mut().zip(lookup) {
+                    *input = input.clone() + other_input;
+                }
+            } else {
+                multiplexed_lookups.push((group, HashSet::from([branch]), lookups.len()));
+                lookups.push((name, lookup));
+            }
         }
-        for (name, lookup) in self.lookups {
+        for (name, lookup) in lookups {
             cs.lookup_any(name, |meta| {
                 lookup
                     .into_iter()
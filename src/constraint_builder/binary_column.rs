@@ -1,4 +1,5 @@
 use super::{BinaryQuery, ConstraintBuilder, Query};
+use crate::Error;
 use halo2_proofs::{
     circuit::{Region, Value},
     halo2curves::ff::FromUniformBytes,
@@ -10,6 +11,10 @@ use halo2_proofs::{
 pub struct BinaryColumn(pub Column<Advice>);
 
 impl BinaryColumn {
+    pub fn name<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, name: &str) {
+        region.name_column(|| name, self.0);
+    }
+
     pub fn rotation<F: FromUniformBytes<64> + Ord>(&self, i: i32) -> BinaryQuery<F> {
         BinaryQuery(Query::Advice(self.0, i))
     }
@@ -43,14 +48,13 @@ impl BinaryColumn {
         region: &mut Region<'_, F>,
         offset: usize,
         value: bool,
-    ) {
-        region
-            .assign_advice(
-                || "binary",
-                self.0,
-                offset,
-                || Value::known(F::from(value as u64)),
-            )
-            .expect("failed assign_advice");
+    ) -> Result<(), Error> {
+        region.assign_advice(
+            || "binary",
+            self.0,
+            offset,
+            || Value::known(F::from(value as u64)),
+        )?;
+        Ok(())
     }
 }
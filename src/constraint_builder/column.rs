@@ -1,4 +1,5 @@
 use super::{BinaryQuery, Query};
+use crate::Error;
 use halo2_proofs::plonk::Assigned;
 use halo2_proofs::{
     circuit::{Region, Value},
@@ -11,6 +12,10 @@ use std::fmt::Debug;
 pub struct SelectorColumn(pub Column<Fixed>);
 
 impl SelectorColumn {
+    pub fn name<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, name: &str) {
+        region.name_column(|| name, self.0);
+    }
+
     pub fn current<F: FromUniformBytes<64> + Ord>(self) -> BinaryQuery<F> {
         self.rotation(0)
     }
@@ -19,10 +24,37 @@ impl SelectorColumn {
         BinaryQuery(Query::Fixed(self.0, i))
     }
 
-    pub fn enable<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, offset: usize) {
-        region
-            .assign_fixed(|| "selector", self.0, offset, || Value::known(F::ONE))
-            .expect("failed enable selector");
+    pub fn enable<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut Region<'_, F>,
+        offset: usize,
+    ) -> Result<(), Error> {
+        region.assign_fixed(|| "selector", self.0, offset, || Value::known(F::ONE))?;
+        Ok(())
+    }
+
+    /// Enables the selector on the rows used by gadgets that need `n_rows_required` rows,
+    /// including the disabled first row, and on `n_padding_rows` rows after them. Returns an
+    /// error instead if these don't fit in the `n_rows` rows of the region.
+    pub fn enable_rows<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut Region<'_, F>,
+        n_rows_required: usize,
+        n_padding_rows: usize,
+        n_rows: usize,
+    ) -> Result<(), Error> {
+        let required = n_rows_required + n_padding_rows;
+        if required > n_rows {
+            return Err(Error::NotEnoughRows {
+                what: "enabled selector rows",
+                required,
+                available: n_rows,
+            });
+        }
+        for offset in 1..required {
+            self.enable(region, offset)?;
+        }
+        Ok(())
     }
 }
 
@@ -30,6 +62,10 @@ impl SelectorColumn {
 pub struct FixedColumn(pub Column<Fixed>);
 
 impl FixedColumn {
+    pub fn name<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, name: &str) {
+        region.name_column(|| name, self.0);
+    }
+
     pub fn rotation<F: FromUniformBytes<64> + Ord>(self, i: i32) -> Query<F> {
         Query::Fixed(self.0, i)
     }
@@ -47,17 +83,13 @@ impl FixedColumn {
         region: &mut Region<'_, F>,
         offset: usize,
         value: T,
-    ) where
+    ) -> Result<(), Error>
+    where
         <T as TryInto<F>>::Error: Debug,
     {
-        region
-            .assign_fixed(
-                || "fixed",
-                self.0,
-                offset,
-                || Value::known(value.try_into().unwrap()),
-            )
-            .expect("failed assign_fixed");
+        let value: F = cell_value(value)?;
+        region.assign_fixed(|| "fixed", self.0, offset, || Value::known(value))?;
+        Ok(())
     }
 }
 
@@ -65,6 +97,10 @@ impl FixedColumn {
 pub struct AdviceColumn(pub Column<Advice>);
 
 impl AdviceColumn {
+    pub fn name<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, name: &str) {
+        region.name_column(|| name, self.0);
+    }
+
     pub fn rotation<F: FromUniformBytes<64> + Ord>(self, i: i32) -> Query<F> {
         Query::Advice(self.0, i)
     }
@@ -90,17 +126,13 @@ impl AdviceColumn {
         region: &mut Region<'_, F>,
         offset: usize,
         value: T,
-    ) where
+    ) -> Result<(), Error>
+    where
         <T as TryInto<F>>::Error: Debug,
     {
-        region
-            .assign_advice(
-                || "advice",
-                self.0,
-                offset,
-                || Value::known(value.try_into().unwrap()),
-            )
-            .expect("failed assign_advice");
+        let value: F = cell_value(value)?;
+        region.assign_advice(|| "advice", self.0, offset, || Value::known(value))?;
+        Ok(())
     }
 
     pub fn assign_rational<F: FromUniformBytes<64> + Ord>(
@@ -108,10 +140,9 @@ impl AdviceColumn {
         region: &mut Region<'_, F>,
         offset: usize,
         value: Assigned<F>,
-    ) {
-        region
-            .assign_advice(|| "advice", self.0, offset, || Value::known(value))
-            .expect("failed assign_advice");
+    ) -> Result<(), Error> {
+        region.assign_advice(|| "advice", self.0, offset, || Value::known(value))?;
+        Ok(())
     }
 }
 
@@ -119,6 +150,10 @@ impl AdviceColumn {
 pub struct SecondPhaseAdviceColumn(pub Column<Advice>);
 
 impl SecondPhaseAdviceColumn {
+    pub fn name<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>, name: &str) {
+        region.name_column(|| name, self.0);
+    }
+
     fn rotation<F: FromUniformBytes<64> + Ord>(self, i: i32) -> Query<F> {
         Query::Advice(self.0, i)
     }
@@ -136,9 +171,19 @@ impl SecondPhaseAdviceColumn {
         region: &mut Region<'_, F>,
         offset: usize,
         value: Value<F>,
-    ) {
-        region
-            .assign_advice(|| "second phase advice", self.0, offset, || value)
-            .expect("failed assign_advice");
+    ) -> Result<(), Error> {
+        region.assign_advice(|| "second phase advice", self.0, offset, || value)?;
+        Ok(())
     }
 }
+
+/// Converts a value to the field element assigned to a cell, returning an error instead of
+/// panicking if it doesn't fit.
+pub(crate) fn cell_value<F, T: TryInto<F>>(value: T) -> Result<F, Error>
+where
+    <T as TryInto<F>>::Error: Debug,
+{
+    value
+        .try_into()
+        .map_err(|error| Error::InvalidCellValue(format!("{error:?}")))
+}
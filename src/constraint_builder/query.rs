@@ -6,7 +6,7 @@ use halo2_proofs::{
     poly::Rotation,
 };
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Query<F: Clone> {
     Constant(F),
     Advice(Column<Advice>, i32),
@@ -42,11 +42,68 @@ impl<F: FromUniformBytes<64> + Ord> Query<F> {
         }
     }
 
+    /// Evaluates the query on a row, given the values of the advice and fixed cells at a
+    /// rotation from that row.
+    #[cfg(feature = "coverage")]
+    pub fn evaluate(
+        &self,
+        advice: &impl Fn(Column<Advice>, i32) -> F,
+        fixed: &impl Fn(Column<Fixed>, i32) -> F,
+        challenge: &impl Fn(Challenge) -> F,
+    ) -> F {
+        let evaluate = |q: &Self| q.evaluate(advice, fixed, challenge);
+        match self {
+            Query::Constant(f) => *f,
+            Query::Advice(c, r) => advice(*c, *r),
+            Query::Fixed(c, r) => fixed(*c, *r),
+            Query::Challenge(c) => challenge(*c),
+            Query::Neg(q) => -evaluate(q),
+            Query::Add(q, u) => evaluate(q) + evaluate(u),
+            Query::Mul(q, u) => evaluate(q) * evaluate(u),
+        }
+    }
+
     pub fn square(self) -> Self {
         self.clone() * self
     }
 }
 
+/// Formats the query as an expression in the indices of its columns, e.g. `advice[3][-1]` for
+/// the previous row of the advice column with index 3.
+impl<F: FromUniformBytes<64> + Ord> std::fmt::Display for Query<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rotation = |r: &i32| match r {
+            0 => String::new(),
+            r => format!("[{r}]"),
+        };
+        match self {
+            Query::Constant(c) => {
+                let small = |c: F| {
+                    let repr = c.to_repr();
+                    let (low, high) = repr.as_ref().split_at(8);
+                    high.iter()
+                        .all(|byte| *byte == 0)
+                        .then(|| u64::from_le_bytes(low.try_into().unwrap()))
+                };
+                match (small(*c), small(-*c)) {
+                    (Some(c), _) => write!(f, "{c}"),
+                    (None, Some(c)) => write!(f, "-{c}"),
+                    (None, None) => write!(f, "{c:?}"),
+                }
+            }
+            Query::Advice(c, r) => write!(f, "advice[{}]{}", c.index(), rotation(r)),
+            Query::Fixed(c, r) => write!(f, "fixed[{}]{}", c.index(), rotation(r)),
+            Query::Challenge(c) => write!(f, "challenge[{}]", c.index()),
+            Query::Neg(q) => write!(f, "-{q}"),
+            Query::Add(q, u) => match u.as_ref() {
+                Query::Neg(u) => write!(f, "({q} - {u})"),
+                u => write!(f, "({q} + {u})"),
+            },
+            Query::Mul(q, u) => write!(f, "{q} * {u}"),
+        }
+    }
+}
+
 impl<F: FromUniformBytes<64> + Ord> From<u64> for Query<F> {
     fn from(x: u64) -> Self {
         Self::Constant(F::from(x))
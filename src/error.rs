@@ -0,0 +1,92 @@
+use crate::MPTProofType;
+use ethers_core::types::U256;
+use halo2_proofs::halo2curves::bn256::Fr;
+
+/// Errors from decoding witnesses and assigning the mpt circuit.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The witness needs more rows than the circuit or region has.
+    #[error(
+        "mpt circuit requires {required} rows for {what}, but only {available} rows are available"
+    )]
+    NotEnoughRows {
+        what: &'static str,
+        required: usize,
+        available: usize,
+    },
+    /// The mpt update at `index` of a batch doesn't start from the root that the update before
+    /// it ends at. `id` is the id of the update, if it has one.
+    #[error(
+        "mpt update {index}{} has old root {old_root:?}, but the previous update has new root \
+         {previous_new_root:?}",
+        id.as_ref().map(|id| format!(" ({id})")).unwrap_or_default()
+    )]
+    UnchainedRoots {
+        index: usize,
+        id: Option<String>,
+        old_root: Fr,
+        previous_new_root: Fr,
+    },
+    /// The trace creates an account with an update other than a nonce, balance, or code size
+    /// update, which the circuit doesn't support.
+    #[error(
+        "creating an account with a {proof_type:?} update is not supported, the first field set \
+         must be the nonce, balance, or code size"
+    )]
+    UnsupportedAccountCreation { proof_type: MPTProofType },
+    /// The trace is an update of another type than the proof type it is converted with.
+    #[error("the trace isn't a {0:?} update")]
+    ProofTypeMismatch(MPTProofType),
+    /// The trace is inconsistent, e.g. its hashes don't match or it updates more than one field.
+    #[error("invalid trace: {0}")]
+    InvalidTrace(&'static str),
+    /// A value assigned to a column can't be converted to a field element.
+    #[error("cannot assign {0} to a cell")]
+    InvalidCellValue(String),
+    /// A proof_type column value that isn't the discriminant of any `MPTProofType`.
+    #[error("{0} is not an mpt proof type")]
+    InvalidProofType(u64),
+    /// The proof type can't be assigned with the enabled features, e.g. CodeHashExists proofs
+    /// with the poseidon-codehash feature.
+    #[error("{0:?} proofs are not supported with the enabled features")]
+    UnsupportedProofType(MPTProofType),
+    /// An encoded `MptLookupRow` has a version other than `MPT_LOOKUP_ROW_VERSION`.
+    #[error("mpt lookup row version {0} is not supported")]
+    UnsupportedLookupRowVersion(u32),
+    /// An encoded `MptLookupRow` or `Claim` has a value that its proof type can't have, e.g. a
+    /// nonce above 2^64 or a poseidon code hash that isn't a field element.
+    #[error("{value} is not a valid {proof_type:?} value")]
+    InvalidValue {
+        proof_type: MPTProofType,
+        value: U256,
+    },
+    /// An encoded `Claim` is invalid for another reason than its proof type or values.
+    #[error("invalid claim encoding: {0}")]
+    InvalidClaimEncoding(&'static str),
+    /// Error from halo2 while assigning a region.
+    #[error(transparent)]
+    Synthesis(#[from] halo2_proofs::plonk::Error),
+}
+
+/// This lets `MptCircuitConfig::assign` be called with `?` from `Circuit::synthesize`.
+impl From<Error> for halo2_proofs::plonk::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Synthesis(error) => error,
+            Error::NotEnoughRows { .. }
+            | Error::UnchainedRoots { .. }
+            | Error::UnsupportedAccountCreation { .. }
+            | Error::ProofTypeMismatch(_)
+            | Error::InvalidTrace(_)
+            | Error::InvalidCellValue(_)
+            | Error::InvalidProofType(_)
+            | Error::UnsupportedProofType(_)
+            | Error::UnsupportedLookupRowVersion(_)
+            | Error::InvalidValue { .. }
+            | Error::InvalidClaimEncoding(_) => {
+                log::error!("{error}");
+                Self::Synthesis
+            }
+        }
+    }
+}
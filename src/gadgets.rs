@@ -2,6 +2,7 @@ pub mod byte_bit;
 pub mod byte_representation;
 pub mod canonical_representation;
 pub mod is_zero;
+pub mod keccak;
 pub mod key_bit;
 pub mod mpt_update;
 pub mod one_hot;
@@ -1,4 +1,5 @@
 use super::super::constraint_builder::{ConstraintBuilder, FixedColumn, Query};
+use crate::Error;
 use halo2_proofs::{circuit::Region, halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
 
 // TODO: fix name to configggggggg
@@ -30,14 +31,23 @@ impl ByteBitGadget {
         Self { byte, index, bit }
     }
 
-    pub fn assign<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>) {
+    pub fn assign<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut Region<'_, F>,
+    ) -> Result<(), Error> {
+        // Cells that are 0 are skipped, because unassigned cells are 0 already.
         let mut offset = 1;
         for byte in 0..256 {
             for index in 0..8 {
-                self.byte.assign(region, offset, byte);
-                self.index.assign(region, offset, index);
-                self.bit
-                    .assign(region, offset, (byte & (1 << index) != 0) as u64);
+                if byte != 0 {
+                    self.byte.assign(region, offset, byte)?;
+                }
+                if index != 0 {
+                    self.index.assign(region, offset, index)?;
+                }
+                if byte & (1 << index) != 0 {
+                    self.bit.assign(region, offset, 1)?;
+                }
                 offset += 1;
             }
         }
@@ -47,6 +57,13 @@ impl ByteBitGadget {
             offset == expected_offset,
             "assign used {offset} rows but {expected_offset} rows expected from `n_rows_required`",
         );
+        Ok(())
+    }
+
+    pub fn name_columns<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>) {
+        self.byte.name(region, "byte_bit byte");
+        self.index.name(region, "byte_bit index");
+        self.bit.name(region, "byte_bit bit");
     }
 
     pub fn n_rows_required() -> usize {
@@ -2,6 +2,7 @@ use super::{byte_bit::RangeCheck256Lookup, is_zero::IsZeroGadget, rlc_randomness
 use crate::constraint_builder::{
     AdviceColumn, ConstraintBuilder, Query, SecondPhaseAdviceColumn, SelectorColumn,
 };
+use crate::Error;
 use halo2_proofs::{
     circuit::{Region, Value},
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
@@ -99,8 +100,8 @@ impl ByteRepresentationConfig {
         u128s: &[u128],
         frs: &[Fr],
         randomness: Value<F>,
-    ) {
-        self.is_first.enable(region, 0);
+    ) -> Result<(), Error> {
+        self.is_first.enable(region, 0)?;
         let byte_representations = u32s
             .iter()
             .map(u32_to_big_endian)
@@ -113,18 +114,25 @@ impl ByteRepresentationConfig {
             let mut value = F::ZERO;
             let mut rlc = Value::known(F::ZERO);
             for (index, byte) in byte_representation.iter().enumerate() {
+                // Cells that are 0 are skipped, because unassigned cells are 0 already.
                 let byte = F::from(u64::from(*byte));
-                self.byte.assign(region, offset, byte);
+                if byte != F::ZERO {
+                    self.byte.assign(region, offset, byte)?;
+                }
 
                 value = value * F::from(256) + byte;
-                self.value.assign(region, offset, value);
+                if value != F::ZERO {
+                    self.value.assign(region, offset, value)?;
+                }
 
                 rlc = rlc * randomness + Value::known(byte);
-                self.rlc.assign(region, offset, rlc);
+                self.rlc.assign(region, offset, rlc)?;
 
-                let index = u64::try_from(index).unwrap();
-                self.index.assign(region, offset, index);
-                self.index_is_zero.assign(region, offset, index);
+                if index != 0 {
+                    let index = u64::try_from(index).unwrap();
+                    self.index.assign(region, offset, index)?;
+                    self.index_is_zero.assign(region, offset, index)?;
+                }
 
                 offset += 1;
             }
@@ -135,6 +143,16 @@ impl ByteRepresentationConfig {
             offset == expected_offset,
             "assign used {offset} rows but {expected_offset} rows expected from `n_rows_required`",
         );
+        Ok(())
+    }
+
+    pub fn name_columns<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>) {
+        self.value.name(region, "byte_representation value");
+        self.rlc.name(region, "byte_representation rlc");
+        self.is_first.name(region, "byte_representation is_first");
+        self.byte.name(region, "byte_representation byte");
+        self.index_is_zero
+            .name_columns(region, "byte_representation index");
     }
 
     pub fn n_rows_required(u32s: &[u32], u64s: &[u64], u128s: &[u128], frs: &[Fr]) -> usize {
@@ -176,6 +194,8 @@ mod test {
         plonk::{Circuit, Error},
     };
 
+    const N_ROWS: usize = 8 * 256 + 1;
+
     #[derive(Clone, Default, Debug)]
     struct TestCircuit {
         u32s: Vec<u32>,
@@ -219,10 +239,17 @@ mod test {
             layouter.assign_region(
                 || "",
                 |mut region| {
-                    for offset in 0..(8 * 256) {
-                        selector.enable(&mut region, offset);
-                    }
-                    byte_bit.assign(&mut region);
+                    let n_rows_required = std::cmp::max(
+                        ByteBitGadget::n_rows_required(),
+                        ByteRepresentationConfig::n_rows_required(
+                            &self.u32s,
+                            &self.u64s,
+                            &self.u128s,
+                            &self.frs,
+                        ),
+                    );
+                    selector.enable_rows(&mut region, n_rows_required, 0, N_ROWS)?;
+                    byte_bit.assign(&mut region)?;
                     byte_representation.assign(
                         &mut region,
                         &self.u32s,
@@ -230,7 +257,7 @@ mod test {
                         &self.u128s,
                         &self.frs,
                         randomness,
-                    );
+                    )?;
                     Ok(())
                 },
             )
@@ -3,6 +3,7 @@ use super::super::constraint_builder::{
     SelectorColumn,
 };
 use super::{byte_bit::RangeCheck256Lookup, is_zero::IsZeroGadget, rlc_randomness::RlcRandomness};
+use crate::Error;
 use ethers_core::k256::elliptic_curve::PrimeField;
 use ethers_core::types::U256;
 use halo2_proofs::{
@@ -11,12 +12,23 @@ use halo2_proofs::{
     halo2curves::bn256::Fr,
     plonk::ConstraintSystem,
 };
-use halo2_proofs::{circuit::Layouter, halo2curves::ff::FromUniformBytes, plonk::Error};
+use halo2_proofs::{circuit::Layouter, halo2curves::ff::FromUniformBytes};
 use itertools::Itertools;
 use num_traits::Zero;
 
 pub trait CanonicalRepresentationLookup {
+    // [value, index, byte], where index is the index of byte in the big endian representation
     fn lookup<F: FromUniformBytes<64> + Ord>(&self) -> [Query<F>; 3];
+
+    // Looks up that byte is byte index of the little endian representation of value.
+    fn lookup_byte<F: FromUniformBytes<64> + Ord>(
+        &self,
+        cb: &mut ConstraintBuilder<F>,
+        name: &'static str,
+        [value, index, byte]: [Query<F>; 3],
+    ) {
+        cb.add_lookup(name, [value, Query::from(31) - index, byte], self.lookup());
+    }
 }
 
 // Lookup to prove that Rlc(x: Fr) = y
@@ -138,48 +150,15 @@ impl CanonicalRepresentationConfig {
         randomness: Value<Fr>,
         values: &[Fr],
         n_rows: usize,
-    ) {
+    ) -> Result<(), Error> {
         let modulus = U256::from_str_radix(Fr::MODULUS, 16).unwrap();
         let mut modulus_bytes = [0u8; 32];
         modulus.to_big_endian(&mut modulus_bytes);
 
         let mut offset = 1;
         for value in values.iter() {
-            let mut bytes = value.to_bytes();
-            bytes.reverse();
-            let mut differences_are_zero_so_far = true;
-            let mut rlc = Value::known(Fr::zero());
-            for (index, (byte, modulus_byte)) in bytes.iter().zip_eq(&modulus_bytes).enumerate() {
-                self.byte.assign(region, offset, u64::from(*byte));
-                self.modulus_byte
-                    .assign(region, offset, u64::from(*modulus_byte));
-
-                self.index
-                    .assign(region, offset, u64::try_from(index).unwrap());
-                if index.is_zero() {
-                    self.index_is_zero.enable(region, offset);
-                } else if index == 31 {
-                    self.index_is_31.enable(region, offset);
-                }
-
-                let difference = Fr::from(u64::from(*modulus_byte)) - Fr::from(u64::from(*byte));
-                self.difference.assign(region, offset, difference);
-                self.difference_is_zero.assign(region, offset, difference);
-
-                self.differences_are_zero_so_far.assign(
-                    region,
-                    offset,
-                    differences_are_zero_so_far,
-                );
-                differences_are_zero_so_far &= difference.is_zero_vartime();
-
-                self.value.assign(region, offset, *value);
-
-                rlc = rlc * randomness + Value::known(Fr::from(u64::from(*byte)));
-                self.rlc.assign(region, offset, rlc);
-
-                offset += 1
-            }
+            self.assign_value(region, offset, *value, randomness, &modulus_bytes)?;
+            offset += 32;
         }
 
         let expected_offset = Self::n_rows_required(values);
@@ -190,28 +169,10 @@ impl CanonicalRepresentationConfig {
 
         let n_padding_values = n_rows / 32 - values.len();
         for _ in 0..n_padding_values {
-            for (index, modulus_byte) in modulus_bytes.iter().enumerate() {
-                self.modulus_byte
-                    .assign(region, offset, u64::from(*modulus_byte));
-
-                self.index
-                    .assign(region, offset, u64::try_from(index).unwrap());
-                if index.is_zero() {
-                    self.index_is_zero.enable(region, offset);
-                } else if index == 31 {
-                    self.index_is_31.enable(region, offset);
-                }
-
-                let difference = Fr::from(u64::from(*modulus_byte));
-                self.difference.assign(region, offset, difference);
-                self.difference_is_zero.assign(region, offset, difference);
-
-                self.differences_are_zero_so_far
-                    .assign(region, offset, index == 0);
-
-                offset += 1
-            }
+            self.assign_value(region, offset, Fr::zero(), randomness, &modulus_bytes)?;
+            offset += 32;
         }
+        Ok(())
     }
 
     pub fn assign_par(
@@ -220,7 +181,7 @@ impl CanonicalRepresentationConfig {
         randomness: Value<Fr>,
         values: &[Fr],
         n_rows: usize,
-    ) {
+    ) -> Result<(), Error> {
         let modulus = U256::from_str_radix(Fr::MODULUS, 16).unwrap();
         let mut modulus_bytes = [0u8; 32];
         modulus.to_big_endian(&mut modulus_bytes);
@@ -241,7 +202,7 @@ impl CanonicalRepresentationConfig {
             .zip(is_first_passes.iter_mut())
             .enumerate()
             .map(|(i, (values, is_first_pass))| {
-                move |mut region: Region<'_, Fr>| -> Result<(), Error> {
+                move |mut region: Region<'_, Fr>| -> Result<(), halo2_proofs::plonk::Error> {
                     let region = &mut region;
                     if *is_first_pass {
                         *is_first_pass = false;
@@ -250,49 +211,14 @@ impl CanonicalRepresentationConfig {
                         } else {
                             values.len() * 32 - 1
                         };
-                        self.value.assign(region, last_off, Fr::zero());
+                        self.value.assign(region, last_off, Fr::zero())?;
                         return Ok(());
                     }
+                    self.name_columns(region);
                     let mut offset = if i == 0 { 1 } else { 0 };
                     for value in values.iter() {
-                        let mut bytes = value.to_bytes();
-                        bytes.reverse();
-                        let mut differences_are_zero_so_far = true;
-                        let mut rlc = Value::known(Fr::zero());
-                        for (index, (byte, modulus_byte)) in
-                            bytes.iter().zip_eq(&modulus_bytes).enumerate()
-                        {
-                            self.byte.assign(region, offset, u64::from(*byte));
-                            self.modulus_byte
-                                .assign(region, offset, u64::from(*modulus_byte));
-
-                            self.index
-                                .assign(region, offset, u64::try_from(index).unwrap());
-                            if index.is_zero() {
-                                self.index_is_zero.enable(region, offset);
-                            } else if index == 31 {
-                                self.index_is_31.enable(region, offset);
-                            }
-
-                            let difference =
-                                Fr::from(u64::from(*modulus_byte)) - Fr::from(u64::from(*byte));
-                            self.difference.assign(region, offset, difference);
-                            self.difference_is_zero.assign(region, offset, difference);
-
-                            self.differences_are_zero_so_far.assign(
-                                region,
-                                offset,
-                                differences_are_zero_so_far,
-                            );
-                            differences_are_zero_so_far &= difference.is_zero_vartime();
-
-                            self.value.assign(region, offset, **value);
-
-                            rlc = rlc * randomness + Value::known(Fr::from(u64::from(*byte)));
-                            self.rlc.assign(region, offset, rlc);
-
-                            offset += 1
-                        }
+                        self.assign_value(region, offset, **value, randomness, &modulus_bytes)?;
+                        offset += 32;
                     }
 
                     Ok(())
@@ -300,9 +226,83 @@ impl CanonicalRepresentationConfig {
             })
             .collect_vec();
 
-        layouter
-            .assign_regions(|| "canonical_repr", assignments)
-            .unwrap();
+        layouter.assign_regions(|| "canonical_repr", assignments)?;
+        Ok(())
+    }
+
+    // Assigns the 32 rows for value, starting at offset. Cells that are 0 are skipped, because
+    // unassigned cells are 0 already.
+    fn assign_value(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+        value: Fr,
+        randomness: Value<Fr>,
+        modulus_bytes: &[u8; 32],
+    ) -> Result<(), Error> {
+        let mut bytes = value.to_bytes();
+        bytes.reverse();
+        let mut differences_are_zero_so_far = true;
+        let mut rlc = Value::known(Fr::zero());
+        for (index, (byte, modulus_byte)) in bytes.iter().zip_eq(modulus_bytes).enumerate() {
+            let offset = offset + index;
+            if *byte != 0 {
+                self.byte.assign(region, offset, u64::from(*byte))?;
+            }
+            if *modulus_byte != 0 {
+                self.modulus_byte
+                    .assign(region, offset, u64::from(*modulus_byte))?;
+            }
+
+            if index.is_zero() {
+                self.index_is_zero.enable(region, offset)?;
+            } else {
+                self.index
+                    .assign(region, offset, u64::try_from(index).unwrap())?;
+                if index == 31 {
+                    self.index_is_31.enable(region, offset)?;
+                }
+            }
+
+            let difference = Fr::from(u64::from(*modulus_byte)) - Fr::from(u64::from(*byte));
+            if !difference.is_zero_vartime() {
+                self.difference.assign(region, offset, difference)?;
+                self.difference_is_zero.assign(region, offset, difference)?;
+            }
+
+            if differences_are_zero_so_far {
+                self.differences_are_zero_so_far
+                    .assign(region, offset, true)?;
+            }
+            differences_are_zero_so_far &= difference.is_zero_vartime();
+
+            if !value.is_zero_vartime() {
+                self.value.assign(region, offset, value)?;
+            }
+
+            rlc = rlc * randomness + Value::known(Fr::from(u64::from(*byte)));
+            self.rlc.assign(region, offset, rlc)?;
+        }
+        Ok(())
+    }
+
+    pub fn name_columns<F: FromUniformBytes<64> + Ord>(&self, region: &mut Region<'_, F>) {
+        self.value.name(region, "canonical_representation value");
+        self.index.name(region, "canonical_representation index");
+        self.byte.name(region, "canonical_representation byte");
+        self.rlc.name(region, "canonical_representation rlc");
+        self.index_is_zero
+            .name(region, "canonical_representation index_is_zero");
+        self.index_is_31
+            .name(region, "canonical_representation index_is_31");
+        self.modulus_byte
+            .name(region, "canonical_representation modulus_byte");
+        self.difference_is_zero
+            .name_columns(region, "canonical_representation difference");
+        self.differences_are_zero_so_far.name(
+            region,
+            "canonical_representation differences_are_zero_so_far",
+        );
     }
 
     pub fn n_rows_required(values: &[Fr]) -> usize {
@@ -339,6 +339,8 @@ mod test {
         plonk::{Circuit, Error},
     };
 
+    const N_ROWS: usize = 8 * 256 + 1;
+
     #[derive(Clone, Default, Debug)]
     struct TestCircuit {
         values: Vec<Fr>,
@@ -379,11 +381,13 @@ mod test {
             layouter.assign_region(
                 || "",
                 |mut region| {
-                    for offset in 1..(1 + 8 * 256) {
-                        selector.enable(&mut region, offset);
-                    }
-                    byte_bit.assign(&mut region);
-                    canonical_representation.assign(&mut region, randomness, &self.values, 256);
+                    let n_rows_required = std::cmp::max(
+                        ByteBitGadget::n_rows_required(),
+                        CanonicalRepresentationConfig::n_rows_required(&self.values),
+                    );
+                    selector.enable_rows(&mut region, n_rows_required, 0, N_ROWS)?;
+                    byte_bit.assign(&mut region)?;
+                    canonical_representation.assign(&mut region, randomness, &self.values, 256)?;
                     Ok(())
                 },
             )
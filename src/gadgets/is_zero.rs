@@ -1,4 +1,5 @@
-use crate::constraint_builder::{AdviceColumn, BinaryQuery, ConstraintBuilder, Query};
+use crate::constraint_builder::{cell_value, AdviceColumn, BinaryQuery, ConstraintBuilder, Query};
+use crate::Error;
 use halo2_proofs::{
     circuit::Region, halo2curves::ff::FromUniformBytes, plonk::Assigned, plonk::ConstraintSystem,
 };
@@ -19,20 +20,32 @@ impl IsZeroGadget {
         BinaryQuery(Query::one() - self.value.previous() * self.inverse_or_zero.previous())
     }
 
+    /// Names the value column `name`, and the inverse column after it.
+    pub fn name_columns<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut Region<'_, F>,
+        name: &str,
+    ) {
+        self.value.name(region, name);
+        self.inverse_or_zero
+            .name(region, &format!("{name} inverse_or_zero"));
+    }
+
     pub fn assign<F: FromUniformBytes<64> + Ord, T: Copy + TryInto<F>>(
         &self,
         region: &mut Region<'_, F>,
         offset: usize,
         value: T,
-    ) where
+    ) -> Result<(), Error>
+    where
         <T as TryInto<F>>::Error: Debug,
     {
         self.inverse_or_zero.assign_rational(
             region,
             offset,
             // invert is deferred and then batched by the real/mock prover
-            Assigned::<F>::from(value.try_into().unwrap()).invert(),
-        );
+            Assigned::<F>::from(cell_value::<F, T>(value)?).invert(),
+        )
     }
 
     // TODO: get rid of assign method in favor of it.
@@ -41,11 +54,12 @@ impl IsZeroGadget {
         region: &mut Region<'_, F>,
         offset: usize,
         value: T,
-    ) where
+    ) -> Result<(), Error>
+    where
         <T as TryInto<F>>::Error: Debug,
     {
-        self.value.assign(region, offset, value);
-        self.assign(region, offset, value);
+        self.value.assign(region, offset, value)?;
+        self.assign(region, offset, value)
     }
 
     pub fn configure<F: FromUniformBytes<64> + Ord>(
@@ -0,0 +1,65 @@
+use crate::constraint_builder::{AdviceColumn, FixedColumn, SecondPhaseAdviceColumn};
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+use crate::Error;
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+use halo2_proofs::{
+    circuit::{Region, Value},
+    halo2curves::bn256::Fr,
+    plonk::{ConstraintSystem, SecondPhase},
+};
+
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+const MAX_KECCAK_ROWS: usize = 20;
+
+/// Lookup represent the keccak table in zkevm circuit
+pub trait KeccakLookup {
+    /// Returns the q_enable, is_final, and output_rlc columns of the table. A row with q_enable
+    /// and is_final set has the rlc of the big-endian bytes of a keccak hash in output_rlc.
+    fn lookup_columns(&self) -> (FixedColumn, AdviceColumn, SecondPhaseAdviceColumn);
+}
+
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+#[derive(Clone, Copy)]
+pub struct KeccakTable {
+    q_enable: FixedColumn,
+    is_final: AdviceColumn,
+    output_rlc: SecondPhaseAdviceColumn,
+}
+
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+impl KeccakTable {
+    pub fn configure(cs: &mut ConstraintSystem<Fr>) -> Self {
+        Self {
+            q_enable: FixedColumn(cs.fixed_column()),
+            is_final: AdviceColumn(cs.advice_column()),
+            output_rlc: SecondPhaseAdviceColumn(cs.advice_column_in(SecondPhase)),
+        }
+    }
+
+    pub fn load(
+        &self,
+        region: &mut Region<'_, Fr>,
+        output_rlcs: &[Value<Fr>],
+    ) -> Result<(), Error> {
+        self.q_enable.name(region, "keccak q_enable");
+        self.is_final.name(region, "keccak is_final");
+        self.output_rlc.name(region, "keccak output_rlc");
+        for (offset, output_rlc) in output_rlcs.iter().enumerate() {
+            self.is_final.assign(region, offset, true)?;
+            self.output_rlc.assign(region, offset, *output_rlc)?;
+        }
+        // As for the test poseidon table, the fixed column doesn't depend on the number of
+        // hashes that are looked up.
+        for offset in 0..MAX_KECCAK_ROWS {
+            self.q_enable.assign(region, offset, Fr::one())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "poseidon-codehash")))]
+impl KeccakLookup for KeccakTable {
+    fn lookup_columns(&self) -> (FixedColumn, AdviceColumn, SecondPhaseAdviceColumn) {
+        (self.q_enable, self.is_final, self.output_rlc)
+    }
+}
@@ -3,6 +3,7 @@ use super::{
     canonical_representation::CanonicalRepresentationLookup,
 };
 use crate::constraint_builder::{AdviceColumn, ConstraintBuilder, Query};
+use crate::Error;
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::{
     circuit::Region,
@@ -55,15 +56,10 @@ impl KeyBitConfig {
             [index_mod_8.current()],
             range_check_8.lookup(),
         );
-        // TODO: standardize endianess to remove this 31 here?
-        cb.add_lookup(
+        representation.lookup_byte(
+            cb,
             "byte in canonical representation",
-            [
-                value.current(),
-                Query::from(31) - index_div_8.current(),
-                byte.current(),
-            ],
-            representation.lookup(),
+            [value.current(), index_div_8.current(), byte.current()],
         );
         cb.add_lookup(
             "bit is correct",
@@ -86,7 +82,11 @@ impl KeyBitConfig {
         }
     }
 
-    pub fn assign(&self, region: &mut Region<'_, Fr>, lookups: &[(Fr, usize, bool)]) {
+    pub fn assign(
+        &self,
+        region: &mut Region<'_, Fr>,
+        lookups: &[(Fr, usize, bool)],
+    ) -> Result<(), Error> {
         self.assign_internal(region, lookups, false)
     }
     pub fn assign_internal(
@@ -94,36 +94,66 @@ impl KeyBitConfig {
         region: &mut Region<'_, Fr>,
         lookups: &[(Fr, usize, bool)],
         use_par: bool,
-    ) {
-        // TODO; dedup lookups
-        for (offset, (value, index, bit)) in lookups.iter().enumerate() {
-            // TODO: either move the disabled row to the end of the assigment or get rid of it entirely.
-            let offset = if !use_par {
-                offset + 1 // Start assigning at offet = 1 because the first row is disabled.
-            } else {
-                offset
-            };
+    ) -> Result<(), Error> {
+        // TODO: either move the disabled row to the end of the assigment or get rid of it entirely.
+        let offset = if !use_par {
+            1 // Start assigning at offet = 1 because the first row is disabled.
+        } else {
+            0
+        };
+        self.append(region, offset, lookups.iter().copied())?;
+        Ok(())
+    }
+
+    /// Assigns `lookups` starting at `offset` and returns the offset of the next unassigned row,
+    /// so that the lookups of a witness can be assigned one chunk of proofs at a time. Lookups
+    /// are not deduplicated across calls.
+    pub fn append(
+        &self,
+        region: &mut Region<'_, Fr>,
+        mut offset: usize,
+        lookups: impl IntoIterator<Item = (Fr, usize, bool)>,
+    ) -> Result<usize, Error> {
+        for (value, index, bit) in lookups {
             let bytes = value.to_bytes();
 
             let index_div_8 = index / 8; // index = (31 - index/8) * 8
             let index_mod_8 = index % 8;
             let byte = bytes[index_div_8];
             // sanity check. TODO: Get rid of bit in the assign fn?
-            assert_eq!(*bit, byte & 1 << index_mod_8 != 0);
-
-            self.value.assign(region, offset, *value);
-            self.index
-                .assign(region, offset, u64::try_from(*index).unwrap());
-            self.bit.assign(region, offset, *bit);
-            self.index_div_8
-                .assign(region, offset, u64::try_from(index_div_8).unwrap());
-            self.index_mod_8
-                .assign(region, offset, u64::try_from(index_mod_8).unwrap());
-            self.byte.assign(region, offset, u64::from(byte));
+            assert_eq!(bit, byte & 1 << index_mod_8 != 0);
+
+            // Cells that are 0 are skipped, because unassigned cells are 0 already.
+            self.value.assign(region, offset, value)?;
+            if index != 0 {
+                self.index
+                    .assign(region, offset, u64::try_from(index).unwrap())?;
+            }
+            if bit {
+                self.bit.assign(region, offset, true)?;
+            }
+            if index_div_8 != 0 {
+                self.index_div_8
+                    .assign(region, offset, u64::try_from(index_div_8).unwrap())?;
+            }
+            if index_mod_8 != 0 {
+                self.index_mod_8
+                    .assign(region, offset, u64::try_from(index_mod_8).unwrap())?;
+            }
+            if byte != 0 {
+                self.byte.assign(region, offset, u64::from(byte))?;
+            }
+
+            offset += 1;
         }
+        Ok(offset)
     }
 
-    pub fn assign_par(&self, layouter: &mut impl Layouter<Fr>, lookups: &[(Fr, usize, bool)]) {
+    pub fn assign_par(
+        &self,
+        layouter: &mut impl Layouter<Fr>,
+        lookups: &[(Fr, usize, bool)],
+    ) -> Result<(), Error> {
         let num_threads = std::thread::available_parallelism()
             .expect("get num threads")
             .get();
@@ -134,7 +164,7 @@ impl KeyBitConfig {
             .zip(is_first_pass.iter_mut())
             .enumerate()
             .map(|(i, (lookups, is_first_pass))| {
-                move |mut region: Region<'_, Fr>| {
+                move |mut region: Region<'_, Fr>| -> Result<(), halo2_proofs::plonk::Error> {
                     if *is_first_pass {
                         *is_first_pass = false;
 
@@ -146,18 +176,29 @@ impl KeyBitConfig {
                             } else {
                                 lookups.len() - 1
                             };
-                            self.byte.assign(&mut region, last_off, 0_u64);
+                            self.byte.assign(&mut region, last_off, 0_u64)?;
                         }
                         return Ok(());
                     }
-                    self.assign_internal(&mut region, lookups, true);
+                    self.name_columns(&mut region);
+                    self.assign_internal(&mut region, lookups, true)?;
 
                     Ok(())
                 }
             })
             .collect_vec();
 
-        layouter.assign_regions(|| "key_bit", assignments).unwrap();
+        layouter.assign_regions(|| "key_bit", assignments)?;
+        Ok(())
+    }
+
+    pub fn name_columns(&self, region: &mut Region<'_, Fr>) {
+        self.value.name(region, "key_bit value");
+        self.index.name(region, "key_bit index");
+        self.bit.name(region, "key_bit bit");
+        self.index_div_8.name(region, "key_bit index_div_8");
+        self.index_mod_8.name(region, "key_bit index_mod_8");
+        self.byte.name(region, "key_bit byte");
     }
 
     pub fn n_rows_required(lookups: &[(Fr, usize, bool)]) -> usize {
@@ -184,15 +225,21 @@ mod test {
     };
     use super::*;
     use crate::constraint_builder::SelectorColumn;
+    use halo2_proofs::halo2curves::group::ff::PrimeField;
     use halo2_proofs::{
         circuit::{Layouter, SimpleFloorPlanner},
         dev::MockProver,
         plonk::{Circuit, Error},
     };
 
+    const N_ROWS: usize = 8 * 256 + 1;
+
     #[derive(Clone, Default, Debug)]
     struct TestCircuit {
         lookups: Vec<(Fr, usize, bool)>,
+        chunk_size: Option<usize>,
+        // index of a lookup whose bit is flipped after it is assigned.
+        flipped_bit: Option<usize>,
     }
 
     impl Circuit<Fr> for TestCircuit {
@@ -240,7 +287,13 @@ mod test {
             config: Self::Config,
             mut layouter: impl Layouter<Fr>,
         ) -> Result<(), Error> {
-            let keys: Vec<_> = self.lookups.iter().map(|lookup| lookup.0).collect();
+            let keys: Vec<_> = self
+                .lookups
+                .iter()
+                .map(|lookup| lookup.0)
+                .sorted()
+                .dedup()
+                .collect();
 
             let (selector, key_bit, byte_bit, canonical_representation, rlc_randomness) = config;
             let randomness = rlc_randomness.value(&layouter);
@@ -248,30 +301,115 @@ mod test {
             layouter.assign_region(
                 || "",
                 |mut region| {
-                    for offset in 1..(1 + 8 * 256) {
-                        selector.enable(&mut region, offset);
-                    }
+                    let n_rows_required = *[
+                        ByteBitGadget::n_rows_required(),
+                        KeyBitConfig::n_rows_required(&self.lookups),
+                        CanonicalRepresentationConfig::n_rows_required(&keys),
+                    ]
+                    .iter()
+                    .max()
+                    .unwrap();
+                    selector.enable_rows(&mut region, n_rows_required, 0, N_ROWS)?;
 
-                    key_bit.assign(&mut region, &self.lookups);
-                    byte_bit.assign(&mut region);
-                    canonical_representation.assign(&mut region, randomness, &keys, 256);
+                    if let Some(chunk_size) = self.chunk_size {
+                        let mut offset = 1;
+                        for chunk in self.lookups.chunks(chunk_size) {
+                            offset = key_bit.append(&mut region, offset, chunk.iter().copied())?;
+                        }
+                    } else {
+                        key_bit.assign(&mut region, &self.lookups)?;
+                    }
+                    if let Some(i) = self.flipped_bit {
+                        key_bit.bit.assign(&mut region, 1 + i, !self.lookups[i].2)?;
+                    }
+                    byte_bit.assign(&mut region)?;
+                    canonical_representation.assign(&mut region, randomness, &keys, 256)?;
                     Ok(())
                 },
             )
         }
     }
 
+    fn test_lookups() -> Vec<(Fr, usize, bool)> {
+        vec![
+            (Fr::one(), 0, true),
+            (Fr::one(), 1, false),
+            (Fr::from(2342341), 10, true),
+            (Fr::from(2342341), 255, false),
+        ]
+    }
+
     #[test]
     fn test_key_bit() {
         let circuit = TestCircuit {
-            lookups: vec![
-                (Fr::one(), 0, true),
-                (Fr::one(), 1, false),
-                (Fr::from(2342341), 10, true),
-                (Fr::from(2342341), 255, false),
-            ],
+            lookups: test_lookups(),
+            chunk_size: None,
+            flipped_bit: None,
         };
         let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
         assert_eq!(prover.verify(), Ok(()));
     }
+
+    #[test]
+    fn test_key_bit_append() {
+        let circuit = TestCircuit {
+            lookups: test_lookups(),
+            chunk_size: Some(3),
+            flipped_bit: None,
+        };
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    // Lookups at the first and last indices, for values with their high bits zero and for the
+    // largest field element.
+    fn boundary_lookups() -> Vec<(Fr, usize, bool)> {
+        let values = [
+            Fr::one(),
+            Fr::from(u64::MAX),
+            Fr::from_u128(1 << 127),
+            -Fr::one(),
+        ];
+        let indices = [0, 1, 7, 8, 127, 128, 252, 253, 254, 255];
+        values
+            .into_iter()
+            .cartesian_product(indices)
+            .map(|(value, index)| {
+                let bit = value.to_bytes()[index / 8] & 1 << (index % 8) != 0;
+                (value, index, bit)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_key_bit_boundaries() {
+        let lookups = boundary_lookups();
+        assert!(lookups.contains(&(-Fr::one(), 253, true)));
+        assert!(lookups.contains(&(-Fr::one(), 255, false)));
+
+        let circuit = TestCircuit {
+            lookups,
+            chunk_size: None,
+            flipped_bit: None,
+        };
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_key_bit_boundaries_flipped() {
+        let lookups = boundary_lookups();
+        for flipped_bit in [0, 9, lookups.len() - 1] {
+            let circuit = TestCircuit {
+                lookups: lookups.clone(),
+                chunk_size: None,
+                flipped_bit: Some(flipped_bit),
+            };
+            let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+            assert!(
+                prover.verify().is_err(),
+                "flipped bit {flipped_bit} verified"
+            );
+        }
+    }
 }
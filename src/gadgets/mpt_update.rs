@@ -1,11 +1,15 @@
 mod nonexistence_proof;
 mod path;
+mod recording_region;
 mod segment;
 mod word_rlc;
 pub use path::PathType;
+use recording_region::RecordingRegion;
 use segment::SegmentType;
 use word_rlc::{assign as assign_word_rlc, configure as configure_word_rlc};
 
+#[cfg(not(feature = "poseidon-codehash"))]
+use super::keccak::KeccakLookup;
 use super::{
     byte_representation::{BytesLookup, RlcLookup},
     canonical_representation::FrRlcLookup,
@@ -19,30 +23,36 @@ use crate::{
     constraint_builder::{
         AdviceColumn, BinaryQuery, ConstraintBuilder, Query, SecondPhaseAdviceColumn,
     },
+    profile::profile,
     types::{
         storage::{StorageLeaf, StorageProof},
         trie::{next_domain, TrieRows},
-        ClaimKind, HashDomain, Proof,
+        AddressHashTrace, ClaimKind, HashDomain, Proof,
     },
-    util::{account_key, domain_hash, lagrange_polynomial, rlc, u256_hi_lo, u256_to_big_endian},
-    MPTProofType,
+    util::{
+        domain_hash, lagrange_polynomial, rlc, u256_hi_lo, DedupVec, PanicContext, CODE_SIZE_SHIFT,
+    },
+    Error, MPTProofType, MptCircuitConfig, MptLookupRow,
 };
-use ethers_core::types::Address;
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::{
     arithmetic::Field,
-    circuit::{Region, Value},
+    circuit::{layouter::RegionLayouter, Region, Value},
     halo2curves::{bn256::Fr, ff::FromUniformBytes, group::ff::PrimeField},
-    plonk::ConstraintSystem,
+    plonk::{Advice, Column, ConstraintSystem},
 };
 use itertools::{izip, Itertools};
 use lazy_static::lazy_static;
+use rayon::prelude::*;
+use std::ops::Range;
 use strum::IntoEnumIterator;
 
 lazy_static! {
     static ref ZERO_PAIR_HASH: Fr = domain_hash(Fr::zero(), Fr::zero(), HashDomain::Pair);
     static ref ZERO_STORAGE_ROOT_KECCAK_CODEHASH_HASH: Fr =
         domain_hash(Fr::zero(), *ZERO_PAIR_HASH, HashDomain::AccountFields);
+    // Columns that proofs are assigned to in a RecordingRegion, to inspect their witness.
+    static ref RECORDING_CONFIG: MptUpdateConfig = MptCircuitConfig::standalone_mpt_update();
 }
 
 pub trait MptUpdateLookup<F: FromUniformBytes<64> + Ord> {
@@ -67,8 +77,8 @@ pub struct MptUpdateConfig {
     key: AdviceColumn,
     other_key: AdviceColumn,
 
-    // TODO: make this a BinaryColumn for readability, even though this actually must already
-    // binary because of the key bit lookup.
+    // TODO: make this a BinaryColumn for readability. It is constrained to be binary on every row
+    // by the "direction is 0 or 1" constraint.
     direction: AdviceColumn,
     sibling: AdviceColumn,
 
@@ -105,6 +115,17 @@ impl<F: FromUniformBytes<64> + Ord> MptUpdateLookup<F> for MptUpdateConfig {
 }
 
 impl MptUpdateConfig {
+    /// Whether the current row is the Start row of an mpt update or a padding row.
+    pub fn is_start<F: FromUniformBytes<64> + Ord>(&self) -> BinaryQuery<F> {
+        self.segment_type.current_matches(&[SegmentType::Start])
+    }
+
+    /// The values of `MptUpdateLookup::lookup` on the Start row of `proof`, i.e. the row that a
+    /// lookup from the state circuit for this mpt update has to match.
+    pub fn lookup_values(proof: &Proof, randomness: Value<Fr>) -> [Value<Fr>; 7] {
+        MptLookupRow::from(proof).values(randomness)
+    }
+
     pub fn configure<F: FromUniformBytes<64> + Ord>(
         cs: &mut ConstraintSystem<F>,
         cb: &mut ConstraintBuilder<F>,
@@ -170,6 +191,23 @@ impl MptUpdateConfig {
                 fr_rlc.lookup(),
             );
         });
+        cb.condition(
+            is_start.clone().and(proof_type.current_matches(&[
+                MPTProofType::NonceChanged,
+                MPTProofType::BalanceChanged,
+                MPTProofType::CodeSizeExists,
+                MPTProofType::PoseidonCodeHashExists,
+                MPTProofType::CodeHashExists,
+                MPTProofType::AccountDoesNotExist,
+                MPTProofType::AccountDestructed,
+            ])),
+            |cb| {
+                cb.assert_zero(
+                    "storage_key_rlc is 0 for account proofs",
+                    storage_key_rlc.current(),
+                );
+            },
+        );
         cb.condition(!is_start, |cb| {
             cb.assert_equal(
                 "proof type does not change",
@@ -208,6 +246,21 @@ impl MptUpdateConfig {
                 );
             },
         );
+        // If the account exists in both the old and new trie, there is no other leaf on its path,
+        // so other_key must be the account key. Otherwise, the nonexistence proof constraints
+        // decide whether other_key is the key of a different leaf (type 1) or the key (type 2).
+        cb.condition(
+            segment_type
+                .current_matches(&[SegmentType::AccountLeaf0])
+                .and(path_type.current_matches(&[PathType::Common])),
+            |cb| {
+                cb.assert_equal(
+                    "other_key = key for existing account",
+                    other_key.current(),
+                    key.current(),
+                );
+            },
+        );
 
         let is_trie =
             segment_type.current_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
@@ -238,6 +291,12 @@ impl MptUpdateConfig {
         cb.condition(!is_trie, |cb| {
             cb.assert_zero("depth is 0 in non-trie segments", depth.current());
         });
+        // The key bit lookups only make direction binary in trie segments, so it is constrained
+        // here for leaf segments as well.
+        cb.assert(
+            "direction is 0 or 1",
+            BinaryQuery(direction.current()).or(!BinaryQuery(direction.current())),
+        );
 
         let config = Self {
             key,
@@ -275,32 +334,45 @@ impl MptUpdateConfig {
                     PathType::ExtensionNew => configure_extension_new(cb, &config, poseidon),
                 }
             };
-            cb.condition(
-                config.path_type.current_matches(&[variant]),
-                conditional_constraints,
-            );
+            cb.gate(variant.into(), |cb| {
+                cb.exclusive_branch("path type", |cb| {
+                    cb.condition(
+                        config.path_type.current_matches(&[variant]),
+                        conditional_constraints,
+                    )
+                })
+            });
         }
 
-        for variant in SegmentType::iter() {
-            let conditional_constraints = |cb: &mut ConstraintBuilder<F>| {
-                cb.assert_zero(
-                    "domain in allowed set for segment type",
-                    segment::domains(variant)
-                        .iter()
-                        .fold(Query::one(), |product, domain| {
-                            product * (config.domain.current() - u64::from(*domain))
-                        }),
-                );
-            };
-            cb.condition(
-                config.segment_type.current_matches(&[variant]),
-                conditional_constraints,
-            );
-        }
+        #[cfg(feature = "audit")]
+        configure_audit(cb, &config, poseidon);
+
+        cb.gate("segment type domains", |cb| {
+            for variant in SegmentType::iter() {
+                let conditional_constraints = |cb: &mut ConstraintBuilder<F>| {
+                    cb.assert_zero(
+                        "domain in allowed set for segment type",
+                        segment::domains(variant)
+                            .iter()
+                            .fold(Query::one(), |product, domain| {
+                                product * (config.domain.current() - u64::from(*domain))
+                            }),
+                    );
+                };
+                cb.exclusive_branch("segment type", |cb| {
+                    cb.condition(
+                        config.segment_type.current_matches(&[variant]),
+                        conditional_constraints,
+                    )
+                });
+            }
+        });
 
         for proof_type in MPTProofType::iter() {
             let conditional_constraints = |cb: &mut ConstraintBuilder<F>| {
                 configure_segment_transitions(cb, &config.segment_type, proof_type);
+                #[cfg(feature = "audit")]
+                configure_backward_segment_transitions(cb, &config.segment_type, proof_type);
                 match proof_type {
                     MPTProofType::NonceChanged => configure_nonce(cb, &config, bytes, poseidon),
                     MPTProofType::BalanceChanged => configure_balance(cb, &config, poseidon, rlc),
@@ -313,6 +385,7 @@ impl MptUpdateConfig {
                     MPTProofType::AccountDoesNotExist => {
                         configure_empty_account(cb, &config, poseidon)
                     }
+                    #[cfg(not(feature = "poseidon-codehash"))]
                     MPTProofType::CodeHashExists => configure_keccak_code_hash(
                         cb,
                         &config,
@@ -321,6 +394,10 @@ impl MptUpdateConfig {
                         rlc,
                         rlc_randomness.query(),
                     ),
+                    #[cfg(feature = "poseidon-codehash")]
+                    MPTProofType::CodeHashExists => {
+                        cb.assert_unreachable("keccak code hash is disabled")
+                    }
                     MPTProofType::StorageChanged => {
                         configure_storage(cb, &config, poseidon, bytes, rlc, rlc_randomness.query())
                     }
@@ -332,25 +409,60 @@ impl MptUpdateConfig {
                         rlc,
                         rlc_randomness.query(),
                     ),
-                    MPTProofType::AccountDestructed => cb.assert_unreachable("unimplemented!"),
+                    MPTProofType::AccountDestructed => configure_self_destruct(cb, &config),
                 }
             };
-            cb.condition(
-                config.proof_type.current_matches(&[proof_type]),
-                conditional_constraints,
-            );
+            cb.gate(proof_type.into(), |cb| {
+                cb.exclusive_branch("proof type", |cb| {
+                    cb.condition(
+                        config.proof_type.current_matches(&[proof_type]),
+                        conditional_constraints,
+                    )
+                })
+            });
         }
 
         config
     }
 
+    /// Looks up the new keccak code hash of every CodeHashExists proof in `keccak`. Only the
+    /// new code hash is looked up. The old one was checked by the update that wrote it.
+    #[cfg(not(feature = "poseidon-codehash"))]
+    pub fn configure_keccak_lookup<F: FromUniformBytes<64> + Ord>(
+        &self,
+        cb: &mut ConstraintBuilder<F>,
+        keccak: &dyn KeccakLookup,
+    ) {
+        cb.condition(
+            self.proof_type
+                .current_matches(&[MPTProofType::CodeHashExists]),
+            |cb| {
+                cb.condition(
+                    self.segment_type
+                        .current_matches(&[SegmentType::AccountLeaf3]),
+                    |cb| {
+                        cb.keccak_lookup(
+                            "new keccak code hash is in keccak table",
+                            self.new_value.current(),
+                            keccak,
+                        )
+                    },
+                )
+            },
+        );
+    }
+
     /// Valid assignment proving that the address 0 doesn't exist in an empty MPT.
-    pub fn assign_padding_row(&self, region: &mut Region<'_, Fr>, offset: usize) {
+    pub fn assign_padding_row(
+        &self,
+        region: &mut Region<'_, Fr>,
+        offset: usize,
+    ) -> Result<(), Error> {
         self.proof_type
-            .assign(region, offset, MPTProofType::AccountDoesNotExist);
-        self.key.assign(region, offset, *ZERO_PAIR_HASH);
-        self.other_key.assign(region, offset, *ZERO_PAIR_HASH);
-        self.domain.assign(region, offset, HashDomain::Pair);
+            .assign(region, offset, MPTProofType::AccountDoesNotExist)?;
+        self.key.assign(region, offset, *ZERO_PAIR_HASH)?;
+        self.other_key.assign(region, offset, *ZERO_PAIR_HASH)?;
+        self.domain.assign(region, offset, HashDomain::Pair)
     }
 
     /// ..
@@ -359,22 +471,25 @@ impl MptUpdateConfig {
         region: &mut Region<'_, Fr>,
         proofs: &[Proof],
         randomness: Value<Fr>,
-    ) -> usize {
+    ) -> Result<usize, Error> {
         let n_rows = proofs.iter().map(|proof| proof.n_rows()).sum();
         let mut offset = 1; // selector on first row is disabled.
-        for proof in proofs {
-            self.assign_single_proof(region, proof, randomness, offset);
+        for (i, proof) in proofs.iter().enumerate() {
+            let _context = PanicContext(format!("assigning {}", proof.name(i)));
+            profile(format_args!("mpt update {i} assignment"), || {
+                self.assign_single_proof(region, proof, randomness, offset)
+            })?;
             offset += proof.n_rows();
             log::debug!("offset: {}", offset);
         }
 
         let expected_offset = Self::n_rows_required(proofs);
-        assert!(
+        debug_assert!(
             offset == expected_offset,
             "assign used {offset} rows but {expected_offset} rows expected from `n_rows_required`",
         );
 
-        n_rows
+        Ok(n_rows)
     }
 
     pub fn assign_single_proof(
@@ -383,21 +498,25 @@ impl MptUpdateConfig {
         proof: &Proof,
         randomness: Value<Fr>,
         mut offset: usize,
-    ) {
+    ) -> Result<(), Error> {
         let proof_type = MPTProofType::from(proof.claim);
-        let storage_key =
-            randomness.map(|r| rlc(&u256_to_big_endian(&proof.claim.storage_key()), r));
+        #[cfg(feature = "poseidon-codehash")]
+        if proof_type == MPTProofType::CodeHashExists {
+            return Err(Error::UnsupportedProofType(proof_type));
+        }
+        let storage_key = randomness.map(|r| rlc(&proof.storage_key_bytes, r));
         let old_value = randomness.map(|r| proof.claim.old_value_assignment(r));
         let new_value = randomness.map(|r| proof.claim.new_value_assignment(r));
 
         for i in 0..proof.n_rows() {
-            self.proof_type.assign(region, offset + i, proof_type);
-            self.storage_key_rlc.assign(region, offset + i, storage_key);
-            self.old_value.assign(region, offset + i, old_value);
-            self.new_value.assign(region, offset + i, new_value);
+            self.proof_type.assign(region, offset + i, proof_type)?;
+            self.storage_key_rlc
+                .assign(region, offset + i, storage_key)?;
+            self.old_value.assign(region, offset + i, old_value)?;
+            self.new_value.assign(region, offset + i, new_value)?;
         }
 
-        let key = account_key(proof.claim.address);
+        let key = proof.account_key;
         let (other_key, other_leaf_data_hash) =
             // checking if type 1 or type 2
             if proof.old.key != key {
@@ -412,25 +531,18 @@ impl MptUpdateConfig {
                 (proof.old.key, proof.new.leaf_data_hash.unwrap_or_default())
             };
         // Assign start row
-        self.segment_type.assign(region, offset, SegmentType::Start);
-        self.path_type.assign(region, offset, PathType::Start);
-        self.old_hash.assign(region, offset, proof.claim.old_root);
-        self.new_hash.assign(region, offset, proof.claim.new_root);
+        self.segment_type
+            .assign(region, offset, SegmentType::Start)?;
+        self.path_type.assign(region, offset, PathType::Start)?;
+        self.old_hash.assign(region, offset, proof.claim.old_root)?;
+        self.new_hash.assign(region, offset, proof.claim.new_root)?;
 
-        self.key.assign(region, offset, key);
-        self.other_key.assign(region, offset, other_key);
-        self.domain.assign(region, offset, HashDomain::Pair);
+        self.key.assign(region, offset, key)?;
+        self.other_key.assign(region, offset, other_key)?;
+        self.domain.assign(region, offset, HashDomain::Pair)?;
 
-        self.intermediate_values[0].assign(
-            region,
-            offset,
-            Fr::from_u128(address_high(proof.claim.address)),
-        );
-        self.intermediate_values[1].assign(
-            region,
-            offset,
-            u64::from(address_low(proof.claim.address)),
-        );
+        self.intermediate_values[0].assign(region, offset, Fr::from_u128(proof.address_high))?;
+        self.intermediate_values[1].assign(region, offset, u64::from(proof.address_low))?;
 
         let rlc_fr = |x: Fr| {
             let mut bytes = x.to_bytes();
@@ -442,127 +554,97 @@ impl MptUpdateConfig {
             region,
             offset,
             rlc_fr(proof.claim.old_root),
-        );
+        )?;
         self.second_phase_intermediate_values[1].assign(
             region,
             offset,
             rlc_fr(proof.claim.new_root),
-        );
+        )?;
 
         offset += 1;
 
         let n_account_trie_rows =
-            self.assign_account_trie_rows(region, offset, &proof.account_trie_rows);
+            self.assign_account_trie_rows(region, offset, &proof.account_trie_rows)?;
         for i in 0..n_account_trie_rows {
-            self.key.assign(region, offset + i, key);
-            self.other_key.assign(region, offset + i, other_key);
+            self.key.assign(region, offset + i, key)?;
+            self.other_key.assign(region, offset + i, other_key)?;
         }
         offset += n_account_trie_rows;
 
-        let final_path_type = proof
-            .address_hash_traces
-            .first()
-            .map(|(_, _, _, _, _, is_padding_open, is_padding_close)| {
-                match (*is_padding_open, *is_padding_close) {
-                    (false, false) => PathType::Common,
-                    (false, true) => PathType::ExtensionOld,
-                    (true, false) => PathType::ExtensionNew,
-                    (true, true) => unreachable!(),
-                }
-            })
-            .unwrap_or(PathType::Common);
-        let (final_old_hash, final_new_hash) = match proof.address_hash_traces.first() {
-            None => (proof.old.hash(), proof.new.hash()),
-            Some((_, _, old_hash, new_hash, _, _, _)) => (*old_hash, *new_hash),
-        };
+        let (final_old_hash, final_new_hash) = final_account_trie_hashes(proof);
 
         if proof.old_account.is_none() && proof.new_account.is_none() {
             offset -= 1;
-            self.is_zero_gadgets[2].assign_value_and_inverse(region, offset, key - other_key);
-            self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, final_old_hash);
+            self.is_zero_gadgets[2].assign_value_and_inverse(region, offset, key - other_key)?;
+            self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, final_old_hash)?;
 
-            self.intermediate_values[3].assign(region, offset, other_leaf_data_hash);
+            self.intermediate_values[3].assign(region, offset, other_leaf_data_hash)?;
 
-            return; // we don't need to assign any leaf rows for empty accounts
+            return Ok(()); // we don't need to assign any leaf rows for empty accounts
         }
 
-        let segment_types = vec![
+        let segment_types = [
             SegmentType::AccountLeaf0,
             SegmentType::AccountLeaf1,
             SegmentType::AccountLeaf2,
             SegmentType::AccountLeaf3,
         ];
 
-        let leaf_path_type = match final_path_type {
-            PathType::Common => {
-                // need to check if the old or new account is type 2 empty
-                match (
-                    final_old_hash.is_zero_vartime(),
-                    final_new_hash.is_zero_vartime(),
-                ) {
-                    (true, true) => unreachable!("proof type must be AccountDoesNotExist"),
-                    (true, false) => PathType::ExtensionNew,
-                    (false, true) => PathType::ExtensionOld,
-                    (false, false) => PathType::Common,
-                }
-            }
-            _ => final_path_type,
-        };
-
-        let directions = match proof_type {
-            MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
-                vec![true, false, false, false]
-            }
-            MPTProofType::BalanceChanged => vec![true, false, false, true],
-            MPTProofType::PoseidonCodeHashExists => vec![true, true],
-            MPTProofType::CodeHashExists => vec![true, false, true, true],
-            MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
-                vec![true, false, true, false]
-            }
-            MPTProofType::AccountDoesNotExist => unreachable!(),
-            MPTProofType::AccountDestructed => unimplemented!(),
-        };
+        let leaf_path_type = account_leaf_path_type(proof);
+        let directions = account_leaf_directions(proof_type);
         let next_offset = offset + directions.len();
 
         let old_hashes = proof
             .old_account_leaf_hashes()
-            .unwrap_or_else(|| vec![final_old_hash; 4]);
+            .unwrap_or([final_old_hash; 4]);
         let new_hashes = proof
             .new_account_leaf_hashes()
-            .unwrap_or_else(|| vec![final_new_hash; 4]);
+            .unwrap_or([final_new_hash; 4]);
         let siblings = proof.account_leaf_siblings();
 
-        for (i, (segment_type, sibling, old_hash, new_hash, direction)) in
-            izip!(segment_types, siblings, old_hashes, new_hashes, directions).enumerate()
+        for (i, (segment_type, sibling, old_hash, new_hash, direction)) in izip!(
+            segment_types,
+            siblings,
+            old_hashes,
+            new_hashes,
+            directions.iter().copied()
+        )
+        .enumerate()
         {
             if i == 0 {
-                self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, old_hash);
-                self.domain.assign(region, offset + i, HashDomain::Leaf);
+                // The nonexistence proof is for the new account if the account leaf is deleted,
+                // and for the old account otherwise.
+                let hash = match leaf_path_type {
+                    PathType::ExtensionOld => new_hash,
+                    _ => old_hash,
+                };
+                self.is_zero_gadgets[3].assign_value_and_inverse(region, offset, hash)?;
+                self.domain.assign(region, offset + i, HashDomain::Leaf)?;
             } else {
                 self.domain
-                    .assign(region, offset + i, HashDomain::AccountFields);
+                    .assign(region, offset + i, HashDomain::AccountFields)?;
             }
-            self.segment_type.assign(region, offset + i, segment_type);
-            self.path_type.assign(region, offset + i, leaf_path_type);
-            self.sibling.assign(region, offset + i, sibling);
-            self.old_hash.assign(region, offset + i, old_hash);
-            self.new_hash.assign(region, offset + i, new_hash);
-            self.direction.assign(region, offset + i, direction);
-            self.key.assign(region, offset + i, key);
-            self.other_key.assign(region, offset + i, other_key);
+            self.segment_type.assign(region, offset + i, segment_type)?;
+            self.path_type.assign(region, offset + i, leaf_path_type)?;
+            self.sibling.assign(region, offset + i, sibling)?;
+            self.old_hash.assign(region, offset + i, old_hash)?;
+            self.new_hash.assign(region, offset + i, new_hash)?;
+            self.direction.assign(region, offset + i, direction)?;
+            self.key.assign(region, offset + i, key)?;
+            self.other_key.assign(region, offset + i, other_key)?;
 
             match segment_type {
                 SegmentType::AccountLeaf0 => {
                     let [.., other_key_column, other_leaf_data_hash_column] =
                         self.intermediate_values;
-                    other_key_column.assign(region, offset, other_key);
-                    other_leaf_data_hash_column.assign(region, offset, other_leaf_data_hash);
+                    other_key_column.assign(region, offset, other_key)?;
+                    other_leaf_data_hash_column.assign(region, offset, other_leaf_data_hash)?;
                 }
                 SegmentType::AccountLeaf3 => {
                     if let ClaimKind::Storage { key, .. } | ClaimKind::IsEmpty(Some(key)) =
                         proof.claim.kind
                     {
-                        self.key.assign(region, offset + 3, proof.storage.key());
+                        self.key.assign(region, offset + 3, proof.storage.key())?;
                         let [storage_key_high, storage_key_low, new_domain, ..] =
                             self.intermediate_values;
                         let [rlc_storage_key_high, rlc_storage_key_low, ..] =
@@ -574,18 +656,19 @@ impl MptUpdateConfig {
                             [storage_key_high, storage_key_low],
                             [rlc_storage_key_high, rlc_storage_key_low],
                             randomness,
-                        );
+                        )?;
                         self.other_key
-                            .assign(region, offset + 3, proof.storage.other_key());
-                        new_domain.assign(region, offset + 3, HashDomain::AccountFields);
+                            .assign(region, offset + 3, proof.storage.other_key())?;
+                        new_domain.assign(region, offset + 3, HashDomain::AccountFields)?;
                     }
                 }
                 _ => {}
             };
         }
-        self.key.assign(region, offset, key);
-        self.other_key.assign(region, offset, other_key);
-        self.is_zero_gadgets[2].assign_value_and_inverse(region, offset, key - other_key);
+        self.key.assign(region, offset, key)?;
+        self.other_key.assign(region, offset, other_key)?;
+        self.is_zero_gadgets[2].assign_value_and_inverse(region, offset, key - other_key)?;
+        #[cfg(not(feature = "poseidon-codehash"))]
         if let ClaimKind::CodeHash { old, new } = proof.claim.kind {
             let [old_high, old_low, new_high, new_low, ..] = self.intermediate_values;
             let [old_rlc_high, old_rlc_low, new_rlc_high, new_rlc_low, ..] =
@@ -598,7 +681,7 @@ impl MptUpdateConfig {
                     [old_high, old_low],
                     [old_rlc_high, old_rlc_low],
                     randomness,
-                );
+                )?;
             }
             if let Some(value) = new {
                 assign_word_rlc(
@@ -608,10 +691,11 @@ impl MptUpdateConfig {
                     [new_high, new_low],
                     [new_rlc_high, new_rlc_low],
                     randomness,
-                );
+                )?;
             }
         };
-        self.assign_storage(region, next_offset, &proof.storage, randomness);
+        self.assign_storage(region, next_offset, &proof.storage, randomness)?;
+        Ok(())
     }
 
     pub(crate) fn assign_par(
@@ -619,14 +703,14 @@ impl MptUpdateConfig {
         layouter: &mut impl Layouter<Fr>,
         proofs: &[Proof],
         randomness: Value<Fr>,
-    ) -> usize {
+    ) -> Result<usize, Error> {
         let mut is_first_passes = vec![true; proofs.len()];
         let update_assignments = proofs
             .iter()
             .zip(is_first_passes.iter_mut())
             .enumerate()
             .map(|(i, (proof, is_first_pass))| {
-                move |mut region: Region<'_, Fr>| {
+                move |mut region: Region<'_, Fr>| -> Result<(), halo2_proofs::plonk::Error> {
                     let n_rows = proof.n_rows();
                     let (first_off, last_off) = if i == 0 {
                         // The first region has (1 + proof.n_rows()) rows
@@ -644,22 +728,190 @@ impl MptUpdateConfig {
                             &mut region,
                             last_off,
                             MPTProofType::AccountDoesNotExist,
-                        );
+                        )?;
 
                         return Ok(());
                     }
-                    self.assign_single_proof(&mut region, proof, randomness, first_off);
+                    self.name_columns(&mut region);
+                    let _context = PanicContext(format!("assigning {}", proof.name(i)));
+                    profile(format_args!("mpt update {i} assignment"), || {
+                        self.assign_single_proof(&mut region, proof, randomness, first_off)
+                    })?;
 
                     Ok(())
                 }
             })
             .collect_vec();
 
-        layouter
-            .assign_regions(|| "mpt updates", update_assignments)
-            .unwrap();
+        layouter.assign_regions(|| "mpt updates", update_assignments)?;
+
+        Ok(proofs.iter().map(|proof| proof.n_rows()).sum())
+    }
+
+    pub fn name_columns(&self, region: &mut Region<'_, Fr>) {
+        for (column, name) in [
+            (self.domain, "domain"),
+            (self.old_hash, "old_hash"),
+            (self.new_hash, "new_hash"),
+            (self.depth, "depth"),
+            (self.key, "key"),
+            (self.other_key, "other_key"),
+            (self.direction, "direction"),
+            (self.sibling, "sibling"),
+        ] {
+            column.name(region, &format!("mpt_update {name}"));
+        }
+        for (column, name) in [
+            (self.old_value, "old_value"),
+            (self.new_value, "new_value"),
+            (self.storage_key_rlc, "storage_key_rlc"),
+        ] {
+            column.name(region, &format!("mpt_update {name}"));
+        }
+        self.proof_type
+            .name_columns(region, "mpt_update proof_type");
+        self.segment_type
+            .name_columns(region, "mpt_update segment_type");
+        self.path_type.name_columns(region, "mpt_update path_type");
+        for (i, column) in self.intermediate_values.iter().enumerate() {
+            column.name(region, &format!("mpt_update intermediate_values[{i}]"));
+        }
+        for (i, column) in self.second_phase_intermediate_values.iter().enumerate() {
+            column.name(
+                region,
+                &format!("mpt_update second_phase_intermediate_values[{i}]"),
+            );
+        }
+        for (i, gadget) in self.is_zero_gadgets.iter().enumerate() {
+            gadget.name_columns(region, &format!("mpt_update is_zero_gadgets[{i}]"));
+        }
+    }
+
+    /// Describes the row at `offset` of the mpt update region assigned by `assign` for
+    /// `proofs`: the index and claim of the proof it belongs to, and its segment type, path type,
+    /// and depth. MockProver failures only report row offsets, so this is for debugging them.
+    pub fn describe_row(proofs: &[Proof], offset: usize) -> String {
+        if offset == 0 {
+            return "disabled first row".to_string();
+        }
+        let mut proof_offset = 1;
+        for (i, proof) in proofs.iter().enumerate() {
+            if offset < proof_offset + proof.n_rows() {
+                let row = offset - proof_offset;
+                return match row_layout(proof) {
+                    Ok(layout) => {
+                        let (segment_type, path_type, depth) = layout[row];
+                        format!(
+                            "{} row {row}: {segment_type:?} segment, {path_type:?} path, depth \
+                             {depth}, {:?}",
+                            proof.name(i),
+                            proof.claim,
+                        )
+                    }
+                    Err(e) => format!("{} row {row}: {e}", proof.name(i)),
+                };
+            }
+            proof_offset += proof.n_rows();
+        }
+        "padding row".to_string()
+    }
+
+    /// Returns the cells of the mpt update region assigned by `assign` for `proofs` as CSV, with
+    /// one line per row giving its offset, proof index, row in the proof, segment type, path
+    /// type, depth, and proof type, followed by the value of every named column. Cells that
+    /// aren't assigned are empty, and second phase cells are `?` if `randomness` is unknown. If
+    /// `proof` is set, only that proof's rows are included.
+    pub fn layout_csv(
+        proofs: &[Proof],
+        proof: Option<usize>,
+        randomness: Value<Fr>,
+    ) -> Result<String, Error> {
+        let mut names = RecordingRegion::default();
+        let mut region = Region::from(&mut names as &mut dyn RegionLayouter<Fr>);
+        RECORDING_CONFIG.name_columns(&mut region);
+
+        let mut csv = "offset,proof,row,segment_type,path_type,depth,proof_type".to_string();
+        for (_, name) in names.named_columns() {
+            csv.push(',');
+            csv.push_str(name);
+        }
+        csv.push('\n');
+        let mut offset = 1;
+        for (i, p) in proofs.iter().enumerate() {
+            if proof.map_or(true, |proof| proof == i) {
+                let recording = Self::record(p, randomness)?;
+                let layout = RECORDING_CONFIG.recorded_layout(&recording, p.n_rows());
+                for (j, (segment_type, path_type, depth)) in layout.iter().enumerate() {
+                    csv.push_str(&format!(
+                        "{},{i},{j},{segment_type:?},{path_type:?},{depth},{:?}",
+                        offset + j,
+                        MPTProofType::from(p.claim),
+                    ));
+                    for (column, _) in names.named_columns() {
+                        csv.push(',');
+                        match recording.cell(*column, j) {
+                            None => {}
+                            Some(None) => csv.push('?'),
+                            Some(Some(value)) => csv.push_str(&format_cell(value)),
+                        }
+                    }
+                    csv.push('\n');
+                }
+            }
+            offset += p.n_rows();
+        }
+        Ok(csv)
+    }
+
+    // Assigns `proof` from offset 0 of a region that records the cells instead of assigning
+    // them in a circuit.
+    fn record(proof: &Proof, randomness: Value<Fr>) -> Result<RecordingRegion, Error> {
+        let mut recording = RecordingRegion::default();
+        let mut region = Region::from(&mut recording as &mut dyn RegionLayouter<Fr>);
+        RECORDING_CONFIG.assign_single_proof(&mut region, proof, randomness, 0)?;
+        Ok(recording)
+    }
 
-        proofs.iter().map(|proof| proof.n_rows()).sum()
+    // Segment type, path type, and depth of the first n_rows rows of a recorded proof.
+    fn recorded_layout(
+        &self,
+        recording: &RecordingRegion,
+        n_rows: usize,
+    ) -> Vec<(SegmentType, PathType, usize)> {
+        (0..n_rows)
+            .map(|offset| {
+                let is_set = |column: Column<Advice>| {
+                    recording.cell(column, offset) == Some(Some(Fr::one()))
+                };
+                let depth = match recording.cell(self.depth.0, offset) {
+                    Some(Some(depth)) => usize::try_from(u64::from_le_bytes(
+                        depth.to_bytes()[..8].try_into().unwrap(),
+                    ))
+                    .unwrap(),
+                    _ => 0,
+                };
+                (
+                    self.segment_type.variant(is_set),
+                    self.path_type.variant(is_set),
+                    depth,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the rows of the mpt update region that `assign` and `assign_par` use for each of
+    /// `proofs`, in the same order. The rows don't depend on the witness values, so they can be
+    /// computed before or after assignment to map rows of the MptTable back to the updates.
+    pub fn proof_rows(proofs: &[Proof]) -> Vec<Range<usize>> {
+        let mut offset = 1; // selector on first row is disabled.
+        proofs
+            .iter()
+            .map(|proof| {
+                let rows = offset..offset + proof.n_rows();
+                offset = rows.end;
+                rows
+            })
+            .collect()
     }
 
     pub fn n_rows_required(proofs: &[Proof]) -> usize {
@@ -672,13 +924,13 @@ impl MptUpdateConfig {
         region: &mut Region<'_, Fr>,
         starting_offset: usize,
         rows: &TrieRows,
-    ) -> usize {
-        let n_rows = self.assign_trie_rows(region, starting_offset, rows);
+    ) -> Result<usize, Error> {
+        let n_rows = self.assign_trie_rows(region, starting_offset, rows)?;
         for i in 0..n_rows {
             self.segment_type
-                .assign(region, starting_offset + i, SegmentType::AccountTrie);
+                .assign(region, starting_offset + i, SegmentType::AccountTrie)?;
         }
-        n_rows
+        Ok(n_rows)
     }
 
     fn assign_storage_trie_rows(
@@ -686,13 +938,13 @@ impl MptUpdateConfig {
         region: &mut Region<'_, Fr>,
         starting_offset: usize,
         rows: &TrieRows,
-    ) -> usize {
-        let n_rows = self.assign_trie_rows(region, starting_offset, rows);
+    ) -> Result<usize, Error> {
+        let n_rows = self.assign_trie_rows(region, starting_offset, rows)?;
         for i in 0..n_rows {
             self.segment_type
-                .assign(region, starting_offset + i, SegmentType::StorageTrie);
+                .assign(region, starting_offset + i, SegmentType::StorageTrie)?;
         }
-        n_rows
+        Ok(n_rows)
     }
 
     fn assign_trie_rows(
@@ -700,12 +952,12 @@ impl MptUpdateConfig {
         region: &mut Region<'_, Fr>,
         starting_offset: usize,
         rows: &TrieRows,
-    ) -> usize {
+    ) -> Result<usize, Error> {
         for (i, row) in rows.0.iter().enumerate() {
             let offset = starting_offset + i;
             self.depth
-                .assign(region, offset, u64::try_from(i + 1).unwrap());
-            self.path_type.assign(region, offset, row.path_type);
+                .assign(region, offset, u64::try_from(i + 1).unwrap())?;
+            self.path_type.assign(region, offset, row.path_type)?;
 
             if let Some(next_row) = rows.0.get(i + 1) {
                 if !matches!(next_row.path_type, PathType::Start | PathType::Common)
@@ -715,7 +967,7 @@ impl MptUpdateConfig {
                         region,
                         offset,
                         next_domain(row.domain, row.direction),
-                    );
+                    )?;
                 }
             }
             for (value, column) in [
@@ -725,10 +977,10 @@ impl MptUpdateConfig {
                 (row.direction.into(), self.direction),
                 (row.domain.into(), self.domain),
             ] {
-                column.assign(region, offset, value);
+                column.assign(region, offset, value)?;
             }
         }
-        rows.len()
+        Ok(rows.len())
     }
 
     fn assign_storage(
@@ -737,9 +989,9 @@ impl MptUpdateConfig {
         offset: usize,
         storage: &StorageProof,
         randomness: Value<Fr>,
-    ) -> usize {
+    ) -> Result<usize, Error> {
         match storage {
-            StorageProof::Root(_) => 0,
+            StorageProof::Root(_) => Ok(0),
             StorageProof::Update {
                 key,
                 trie_rows,
@@ -748,7 +1000,7 @@ impl MptUpdateConfig {
                 ..
             } => {
                 let other_key = storage.other_key();
-                let n_trie_rows = self.assign_storage_trie_rows(region, offset, trie_rows);
+                let n_trie_rows = self.assign_storage_trie_rows(region, offset, trie_rows)?;
                 let n_leaf_rows = self.assign_storage_leaf_row(
                     region,
                     offset + n_trie_rows,
@@ -757,15 +1009,15 @@ impl MptUpdateConfig {
                     old_leaf,
                     new_leaf,
                     randomness,
-                );
+                )?;
                 let n_rows = n_trie_rows + n_leaf_rows;
 
                 for i in 0..n_rows {
-                    self.key.assign(region, offset + i, *key);
-                    self.other_key.assign(region, offset + i, other_key);
+                    self.key.assign(region, offset + i, *key)?;
+                    self.other_key.assign(region, offset + i, other_key)?;
                 }
 
-                n_rows
+                Ok(n_rows)
             }
         }
     }
@@ -778,7 +1030,7 @@ impl MptUpdateConfig {
         other_key: Fr,
         old: &StorageLeaf,
         new: &StorageLeaf,
-    ) -> usize {
+    ) -> Result<usize, Error> {
         let [_, _, _, other_leaf_data_hash, ..] = self.intermediate_values;
         let [.., key_equals_other_key, hash_is_zero] = self.is_zero_gadgets;
         match (old, new) {
@@ -794,14 +1046,14 @@ impl MptUpdateConfig {
             ) => {
                 assert!(key != other_key);
 
-                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key);
+                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key)?;
 
                 assert_eq!(new_key, old_key);
                 assert_eq!(old_value_hash, new_value_hash);
 
-                hash_is_zero.assign_value_and_inverse(region, offset, old.hash());
+                hash_is_zero.assign_value_and_inverse(region, offset, old.hash())?;
 
-                other_leaf_data_hash.assign(region, offset, *old_value_hash);
+                other_leaf_data_hash.assign(region, offset, *old_value_hash)?;
             }
             (StorageLeaf::Empty { .. }, StorageLeaf::Empty { .. }) => {
                 assert!(key == other_key);
@@ -809,14 +1061,14 @@ impl MptUpdateConfig {
                 assert_eq!(old.hash(), Fr::zero());
                 assert_eq!(new.hash(), Fr::zero());
 
-                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key);
+                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key)?;
             }
-            (StorageLeaf::Entry { .. }, _) | (_, StorageLeaf::Entry { .. }) => return 0,
+            (StorageLeaf::Entry { .. }, _) | (_, StorageLeaf::Entry { .. }) => return Ok(0),
             (StorageLeaf::Leaf { .. }, StorageLeaf::Empty { .. })
             | (StorageLeaf::Empty { .. }, StorageLeaf::Leaf { .. }) => unreachable!(),
         }
 
-        0
+        Ok(0)
     }
 
     fn assign_storage_leaf_row(
@@ -828,34 +1080,22 @@ impl MptUpdateConfig {
         old: &StorageLeaf,
         new: &StorageLeaf,
         randomness: Value<Fr>,
-    ) -> usize {
-        let path_type = match (old, new) {
-            (StorageLeaf::Entry { .. }, StorageLeaf::Entry { .. }) => PathType::Common,
-            (StorageLeaf::Entry { .. }, _) => PathType::ExtensionOld,
-            (_, StorageLeaf::Entry { .. }) => PathType::ExtensionNew,
-            _ => {
-                return self.assign_empty_storage_proof(
-                    region,
-                    offset - 1,
-                    key,
-                    other_key,
-                    old,
-                    new,
-                )
-            }
+    ) -> Result<usize, Error> {
+        let Some(path_type) = storage_leaf_path_type(old, new) else {
+            return self.assign_empty_storage_proof(region, offset - 1, key, other_key, old, new);
         };
-        self.path_type.assign(region, offset, path_type);
+        self.path_type.assign(region, offset, path_type)?;
         self.segment_type
-            .assign(region, offset, SegmentType::StorageLeaf0);
-        self.direction.assign(region, offset, true);
-        self.domain.assign(region, offset, HashDomain::Leaf);
+            .assign(region, offset, SegmentType::StorageLeaf0)?;
+        self.direction.assign(region, offset, true)?;
+        self.domain.assign(region, offset, HashDomain::Leaf)?;
 
         let sibling = match path_type {
             PathType::Start => unreachable!(),
             PathType::Common | PathType::ExtensionOld => old.key(),
             PathType::ExtensionNew => new.key(),
         };
-        self.sibling.assign(region, offset, sibling);
+        self.sibling.assign(region, offset, sibling)?;
 
         let (old_hash, new_hash) = match path_type {
             PathType::Start => unreachable!(),
@@ -863,8 +1103,8 @@ impl MptUpdateConfig {
             PathType::ExtensionOld => (old.value_hash(), new.hash()),
             PathType::ExtensionNew => (old.hash(), new.value_hash()),
         };
-        self.old_hash.assign(region, offset, old_hash);
-        self.new_hash.assign(region, offset, new_hash);
+        self.old_hash.assign(region, offset, old_hash)?;
+        self.new_hash.assign(region, offset, new_hash)?;
 
         let [old_high, old_low, new_high, new_low, ..] = self.intermediate_values;
         let [old_rlc_high, old_rlc_low, new_rlc_high, new_rlc_low, ..] =
@@ -878,7 +1118,7 @@ impl MptUpdateConfig {
                 [old_high, old_low],
                 [old_rlc_high, old_rlc_low],
                 randomness,
-            );
+            )?;
         }
 
         if let StorageLeaf::Entry { .. } = new {
@@ -889,7 +1129,7 @@ impl MptUpdateConfig {
                 [new_high, new_low],
                 [new_rlc_high, new_rlc_low],
                 randomness,
-            );
+            )?;
         }
 
         let [old_hash_is_zero_storage_hash, new_hash_is_zero_storage_hash, ..] =
@@ -898,12 +1138,12 @@ impl MptUpdateConfig {
             region,
             offset,
             old_hash - *ZERO_PAIR_HASH,
-        );
+        )?;
         new_hash_is_zero_storage_hash.assign_value_and_inverse(
             region,
             offset,
             new_hash - *ZERO_PAIR_HASH,
-        );
+        )?;
 
         match path_type {
             PathType::Start => unreachable!(),
@@ -913,12 +1153,12 @@ impl MptUpdateConfig {
                 let other_key = if key != new_key { new_key } else { old.key() };
 
                 let [.., key_equals_other_key, new_hash_is_zero] = self.is_zero_gadgets;
-                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key);
-                new_hash_is_zero.assign_value_and_inverse(region, offset, new_hash);
+                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key)?;
+                new_hash_is_zero.assign_value_and_inverse(region, offset, new_hash)?;
 
                 if key != other_key {
                     let [.., other_leaf_data_hash] = self.intermediate_values;
-                    other_leaf_data_hash.assign(region, offset, new.value_hash());
+                    other_leaf_data_hash.assign(region, offset, new.value_hash())?;
                 }
             }
             PathType::ExtensionNew => {
@@ -926,17 +1166,89 @@ impl MptUpdateConfig {
                 let other_key = if key != old_key { old_key } else { new.key() };
 
                 let [.., key_equals_other_key, old_hash_is_zero] = self.is_zero_gadgets;
-                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key);
-                old_hash_is_zero.assign_value_and_inverse(region, offset, old_hash);
+                key_equals_other_key.assign_value_and_inverse(region, offset, key - other_key)?;
+                old_hash_is_zero.assign_value_and_inverse(region, offset, old_hash)?;
 
                 if key != other_key {
                     let [.., other_leaf_data_hash] = self.intermediate_values;
-                    other_leaf_data_hash.assign(region, offset, old.value_hash());
+                    other_leaf_data_hash.assign(region, offset, old.value_hash())?;
                 }
             }
         }
 
-        1
+        Ok(1)
+    }
+}
+
+fn final_account_trie_hashes(proof: &Proof) -> (Fr, Fr) {
+    match proof.address_hash_traces.first() {
+        None => (proof.old.hash(), proof.new.hash()),
+        Some(trace) => (trace.open, trace.close),
+    }
+}
+
+fn account_leaf_path_type(proof: &Proof) -> PathType {
+    let final_path_type = proof
+        .address_hash_traces
+        .first()
+        .map(AddressHashTrace::path_type)
+        .unwrap_or(PathType::Common);
+    match final_path_type {
+        PathType::Common => {
+            // need to check if the old or new account is type 2 empty
+            let (final_old_hash, final_new_hash) = final_account_trie_hashes(proof);
+            match (
+                final_old_hash.is_zero_vartime(),
+                final_new_hash.is_zero_vartime(),
+            ) {
+                (true, true) => unreachable!("proof type must be AccountDoesNotExist"),
+                (true, false) => PathType::ExtensionNew,
+                (false, true) => PathType::ExtensionOld,
+                (false, false) => PathType::Common,
+            }
+        }
+        _ => final_path_type,
+    }
+}
+
+fn account_leaf_directions(proof_type: MPTProofType) -> &'static [bool] {
+    match proof_type {
+        MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => &[true, false, false, false],
+        MPTProofType::BalanceChanged => &[true, false, false, true],
+        MPTProofType::PoseidonCodeHashExists => &[true, true],
+        MPTProofType::CodeHashExists => &[true, false, true, true],
+        MPTProofType::StorageChanged | MPTProofType::StorageDoesNotExist => {
+            &[true, false, true, false]
+        }
+        MPTProofType::AccountDoesNotExist => unreachable!(),
+        MPTProofType::AccountDestructed => &[true],
+    }
+}
+
+// Returns None if neither leaf is an entry, in which case there is no storage leaf row.
+fn storage_leaf_path_type(old: &StorageLeaf, new: &StorageLeaf) -> Option<PathType> {
+    match (old, new) {
+        (StorageLeaf::Entry { .. }, StorageLeaf::Entry { .. }) => Some(PathType::Common),
+        (StorageLeaf::Entry { .. }, _) => Some(PathType::ExtensionOld),
+        (_, StorageLeaf::Entry { .. }) => Some(PathType::ExtensionNew),
+        _ => None,
+    }
+}
+
+// Segment type, path type, and depth of the rows that assign_single_proof assigns for the proof,
+// which are read from a recording of the assignment.
+fn row_layout(proof: &Proof) -> Result<Vec<(SegmentType, PathType, usize)>, Error> {
+    let recording = MptUpdateConfig::record(proof, Value::unknown())?;
+    Ok(RECORDING_CONFIG.recorded_layout(&recording, proof.n_rows()))
+}
+
+// Small values are written in decimal, and others in hex.
+fn format_cell(value: Fr) -> String {
+    let bytes = value.to_bytes();
+    if bytes[8..].iter().all(|byte| *byte == 0) {
+        u64::from_le_bytes(bytes[..8].try_into().unwrap()).to_string()
+    } else {
+        format!("{value:?}")
     }
 }
 
@@ -980,6 +1292,101 @@ fn configure_segment_transitions<F: FromUniformBytes<64> + Ord>(
     }
 }
 
+// Redundant constraints, which are implied by the ones above, to cross-check them in review and
+// in differential soundness tests.
+#[cfg(feature = "audit")]
+fn configure_audit<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+    poseidon: &impl PoseidonLookup,
+) {
+    let backward_transitions = path::backward_transitions();
+    for variant in PathType::iter().filter(|variant| *variant != PathType::Start) {
+        cb.condition(config.path_type.current_matches(&[variant]), |cb| {
+            cb.assert(
+                "audit: transition for previous path_type -> path_type",
+                config
+                    .path_type
+                    .previous_matches(backward_transitions.get(&variant).unwrap()),
+            );
+        });
+    }
+    // The common path hashes are looked up again from the row after each child row, i.e. with
+    // the rotations of configure_common_path shifted by one. The old and new roots on the Start
+    // row are then recomputed both from the rows below them and from the rows above the leaves.
+    let direction = config.direction.previous();
+    let sibling = config.sibling.previous();
+    let children = |hash: Query<F>| {
+        [
+            direction.clone() * sibling.clone() + (Query::one() - direction.clone()) * hash.clone(),
+            direction.clone() * hash + (Query::one() - direction.clone()) * sibling.clone(),
+        ]
+    };
+    cb.condition(
+        config.path_type.previous_matches(&[PathType::Common]).and(
+            config
+                .path_type
+                .current_matches(&[PathType::Common, PathType::Start]),
+        ),
+        |cb| {
+            let [old_left, old_right] = children(config.old_hash.previous());
+            cb.poseidon_lookup(
+                "audit: poseidon hash correct for old common path from child row",
+                [
+                    old_left,
+                    old_right,
+                    config.domain.previous(),
+                    config.old_hash.rotation(-2),
+                ],
+                poseidon,
+            );
+            let [new_left, new_right] = children(config.new_hash.previous());
+            cb.poseidon_lookup(
+                "audit: poseidon hash correct for new common path from child row",
+                [
+                    new_left,
+                    new_right,
+                    config.domain.previous(),
+                    config.new_hash.rotation(-2),
+                ],
+                poseidon,
+            );
+        },
+    );
+    cb.condition(
+        config
+            .segment_type
+            .previous_matches(&[SegmentType::Start])
+            .and(!config.segment_type.current_matches(&[SegmentType::Start])),
+        |cb| {
+            cb.assert_equal(
+                "audit: key is the one bound to the address on the Start row",
+                config.key.current(),
+                config.key.previous(),
+            );
+        },
+    );
+}
+
+#[cfg(feature = "audit")]
+fn configure_backward_segment_transitions<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    segment: &OneHot<SegmentType>,
+    proof: MPTProofType,
+) {
+    let backward_transitions = segment::backward_transitions(proof);
+    for variant in SegmentType::iter() {
+        if let Some(previous_segments) = backward_transitions.get(&variant) {
+            cb.condition(segment.current_matches(&[variant]), |cb| {
+                cb.assert(
+                    "audit: transition for previous segment -> current segment",
+                    segment.previous_matches(previous_segments),
+                );
+            });
+        }
+    }
+}
+
 fn configure_common_path<F: FromUniformBytes<64> + Ord>(
     cb: &mut ConstraintBuilder<F>,
     config: &MptUpdateConfig,
@@ -1185,7 +1592,7 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     poseidon: &impl PoseidonLookup,
 ) {
     cb.assert(
-        "can only delete existing storage trie nodes for storage proofs",
+        "can only delete existing trie nodes for storage and self destruct proofs",
         config
             .proof_type
             .current_matches(&[MPTProofType::StorageChanged])
@@ -1193,7 +1600,15 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
                 config
                     .segment_type
                     .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0]),
-            ),
+            )
+            .or(config
+                .proof_type
+                .current_matches(&[MPTProofType::AccountDestructed])
+                .and(
+                    config
+                        .segment_type
+                        .current_matches(&[SegmentType::AccountTrie, SegmentType::AccountLeaf0]),
+                )),
     );
     cb.assert_zero(
         "new value is 0 when deleting node",
@@ -1215,28 +1630,31 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
         poseidon,
     );
     cb.assert(
-        "common -> extension old switch only allowed in storage trie segments",
+        "common -> extension old switch only allowed in trie segments",
         config
             .path_type
             .previous_matches(&[PathType::ExtensionOld])
-            .or(config
-                .segment_type
-                .current_matches(&[SegmentType::StorageTrie, SegmentType::StorageLeaf0])),
+            .or(config.segment_type.current_matches(&[
+                SegmentType::AccountTrie,
+                SegmentType::AccountLeaf0,
+                SegmentType::StorageTrie,
+                SegmentType::StorageLeaf0,
+            ])),
     );
-    let is_storage_trie_segment = config
+    let is_trie_segment = config
         .segment_type
-        .current_matches(&[SegmentType::StorageTrie]);
-    cb.condition(is_storage_trie_segment, |cb| {
-        let is_final_storage_trie_segment = config
+        .current_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
+    cb.condition(is_trie_segment, |cb| {
+        let is_final_trie_segment = !config
             .segment_type
-            .next_matches(&[SegmentType::StorageLeaf0]);
-        cb.condition(!is_final_storage_trie_segment.clone(), |cb| {
+            .next_matches(&[SegmentType::AccountTrie, SegmentType::StorageTrie]);
+        cb.condition(!is_final_trie_segment.clone(), |cb| {
             cb.assert_zero(
                 "sibling is zero for non-final old extension path segments",
                 config.sibling.current(),
             );
         });
-        cb.condition(is_final_storage_trie_segment, |cb| {
+        cb.condition(is_final_trie_segment, |cb| {
             cb.assert_equal(
                 "sibling is new leaf hash for final new extension path segments",
                 config.sibling.current(),
@@ -1247,7 +1665,7 @@ fn configure_extension_old<F: FromUniformBytes<64> + Ord>(
     cb.condition(
         config
             .segment_type
-            .current_matches(&[SegmentType::StorageLeaf0]),
+            .current_matches(&[SegmentType::AccountLeaf0, SegmentType::StorageLeaf0]),
         |cb| {
             let [.., key_equals_other_key, new_hash_is_zero] = config.is_zero_gadgets;
             let [.., other_leaf_data_hash] = config.intermediate_values;
@@ -1272,10 +1690,11 @@ fn configure_extension_new<F: FromUniformBytes<64> + Ord>(
     poseidon: &impl PoseidonLookup,
 ) {
     cb.assert(
-        "can only add new nodes for nonce, balance and storage proofs",
+        "can only add new nodes for nonce, balance, code size and storage proofs",
         config.proof_type.current_matches(&[
             MPTProofType::NonceChanged,
             MPTProofType::BalanceChanged,
+            MPTProofType::CodeSizeExists,
             MPTProofType::StorageChanged,
         ]),
     );
@@ -1427,7 +1846,7 @@ fn configure_nonce<F: FromUniformBytes<64> + Ord>(
                 cb.assert_zero("direction is 0", config.direction.current());
 
                 let new_code_size = (config.new_hash.current() - config.new_value.current())
-                    * Query::Constant(F::from(1 << 32).square().invert().unwrap());
+                    * Query::Constant(F::from_u128(CODE_SIZE_SHIFT).invert().unwrap());
                 cb.add_lookup(
                     "new nonce is 8 bytes",
                     [config.new_value.current(), Query::from(7)],
@@ -1443,7 +1862,7 @@ fn configure_nonce<F: FromUniformBytes<64> + Ord>(
                         );
                         let old_code_size = (config.old_hash.current()
                             - config.old_value.current())
-                            * Query::Constant(F::from(1 << 32).square().invert().unwrap());
+                            * Query::Constant(F::from_u128(CODE_SIZE_SHIFT).invert().unwrap());
                         cb.assert_equal(
                             "old_code_size = new_code_size for nonce update",
                             old_code_size.clone(),
@@ -1526,36 +1945,75 @@ fn configure_code_size<F: FromUniformBytes<64> + Ord>(
             }
             SegmentType::AccountLeaf1 => {
                 cb.assert_zero("direction is 0", config.direction.current());
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_zero(
+                            "poseidon code hash is 0 for code size extension new at AccountLeaf1",
+                            config.sibling.current(),
+                        )
+                    },
+                );
             }
             SegmentType::AccountLeaf2 => {
                 cb.assert_zero("direction is 0", config.direction.current());
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_equal(
+                            "sibling is hash(0, hash(0, 0)) for code size extension new at AccountLeaf2",
+                            config.sibling.current(),
+                            Query::from(*ZERO_STORAGE_ROOT_KECCAK_CODEHASH_HASH),
+                        );
+                    },
+                );
             }
             SegmentType::AccountLeaf3 => {
                 cb.assert_zero("direction is 0", config.direction.current());
 
-                let old_nonce = config.old_hash.current()
-                    - config.old_value.current() * Query::Constant(F::from(1 << 32).square());
                 let new_nonce = config.new_hash.current()
-                    - config.new_value.current() * Query::Constant(F::from(1 << 32).square());
-                cb.add_lookup(
-                    "old code size is 8 bytes",
-                    [config.old_value.current(), Query::from(7)],
-                    bytes.lookup(),
-                );
+                    - config.new_value.current() * Query::Constant(F::from_u128(CODE_SIZE_SHIFT));
                 cb.add_lookup(
                     "new code size is 8 bytes",
                     [config.new_value.current(), Query::from(7)],
                     bytes.lookup(),
                 );
-                cb.assert_equal(
-                    "old nonce = new nonce for code size update",
-                    old_nonce.clone(),
-                    new_nonce,
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::Common]),
+                    |cb| {
+                        let old_nonce = config.old_hash.current()
+                            - config.old_value.current()
+                                * Query::Constant(F::from_u128(CODE_SIZE_SHIFT));
+                        cb.add_lookup(
+                            "old code size is 8 bytes",
+                            [config.old_value.current(), Query::from(7)],
+                            bytes.lookup(),
+                        );
+                        cb.assert_equal(
+                            "old nonce = new nonce for code size update",
+                            old_nonce.clone(),
+                            new_nonce.clone(),
+                        );
+                        cb.add_lookup(
+                            "nonce is 8 bytes",
+                            [old_nonce, Query::from(7)],
+                            bytes.lookup(),
+                        );
+                    },
                 );
-                cb.add_lookup(
-                    "nonce is 8 bytes",
-                    [old_nonce, Query::from(7)],
-                    bytes.lookup(),
+                cb.condition(
+                    config.path_type.current_matches(&[PathType::ExtensionNew]),
+                    |cb| {
+                        cb.assert_zero(
+                            "old code size is 0 for ExtensionNew code size update",
+                            config.old_value.current(),
+                        );
+                        cb.assert_zero("nonce is 0 for ExtensionNew code size update", new_nonce);
+                        cb.assert_zero(
+                            "balance is 0 for ExtensionNew code size update",
+                            config.sibling.current(),
+                        );
+                    },
                 );
             }
             _ => {}
@@ -1727,6 +2185,7 @@ fn configure_poseidon_code_hash<F: FromUniformBytes<64> + Ord>(
     }
 }
 
+#[cfg(not(feature = "poseidon-codehash"))]
 fn configure_keccak_code_hash<F: FromUniformBytes<64> + Ord>(
     cb: &mut ConstraintBuilder<F>,
     config: &MptUpdateConfig,
@@ -1984,6 +2443,34 @@ fn configure_empty_storage<F: FromUniformBytes<64> + Ord>(
     }
 }
 
+fn configure_self_destruct<F: FromUniformBytes<64> + Ord>(
+    cb: &mut ConstraintBuilder<F>,
+    config: &MptUpdateConfig,
+) {
+    cb.assert_zero("old value is 0", config.old_value.current());
+    cb.assert_zero("new value is 0", config.new_value.current());
+    cb.condition(
+        config
+            .segment_type
+            .current_matches(&[SegmentType::AccountLeaf0]),
+        |cb| {
+            // The poseidon lookup for the old path in configure_extension_old then shows that
+            // the deleted leaf is the account's, and the nonexistence proof there shows that the
+            // node replacing it is either empty or a leaf for a different key.
+            cb.assert(
+                "account leaf is deleted",
+                config.path_type.current_matches(&[PathType::ExtensionOld]),
+            );
+            cb.assert_equal("direction is 1", config.direction.current(), Query::one());
+            cb.assert_equal(
+                "sibling is key for deleted account leaf",
+                config.sibling.current(),
+                config.key.current(),
+            );
+        },
+    );
+}
+
 fn configure_empty_account<F: FromUniformBytes<64> + Ord>(
     cb: &mut ConstraintBuilder<F>,
     config: &MptUpdateConfig,
@@ -2035,97 +2522,96 @@ fn configure_empty_account<F: FromUniformBytes<64> + Ord>(
     }
 }
 
-fn address_high(a: Address) -> u128 {
-    let high_bytes: [u8; 16] = a.0[..16].try_into().unwrap();
-    u128::from_be_bytes(high_bytes)
-}
-
-fn address_low(a: Address) -> u32 {
-    let low_bytes: [u8; 4] = a.0[16..].try_into().unwrap();
-    u32::from_be_bytes(low_bytes)
-}
-
 // ... the return traces: ([inp;2], domain, hash)
 pub fn hash_traces(proofs: &[Proof]) -> Vec<([Fr; 2], Fr, Fr)> {
-    let mut hash_traces = vec![(
+    let mut hash_traces = DedupVec::default();
+    hash_traces.push((
         [Fr::zero(), Fr::zero()],
         HashDomain::Pair.into(),
         *ZERO_PAIR_HASH,
-    )];
-    for proof in proofs.iter() {
-        for (left, right, domain, hash) in proof.account_trie_rows.poseidon_lookups() {
-            hash_traces.push(([left, right], Fr::from(domain), hash));
-        }
+    ));
+    // Evaluating the poseidon hashes dominates, so the hash traces of each chunk of proofs are
+    // computed in parallel, and only deduplicated serially.
+    for chunk in proofs.chunks(HASH_TRACES_CHUNK_SIZE) {
+        let chunk_hash_traces: Vec<_> = chunk.par_iter().map(proof_hash_traces).collect();
+        hash_traces.extend(chunk_hash_traces.into_iter().flatten());
+    }
+    hash_traces.into_sorted_vec()
+}
 
-        hash_traces.extend(
-            proof
-                .storage
-                .poseidon_lookups()
-                .into_iter()
-                .map(|(left, right, domain, h)| ([left, right], Fr::from(domain), h)),
-        );
+const HASH_TRACES_CHUNK_SIZE: usize = 1 << 10;
+
+fn proof_hash_traces(proof: &Proof) -> Vec<([Fr; 2], Fr, Fr)> {
+    let mut poseidon_lookups = vec![];
+    proof
+        .account_trie_rows
+        .poseidon_lookups(&mut poseidon_lookups);
+    proof.storage.poseidon_lookups(&mut poseidon_lookups);
+    let mut hash_traces: Vec<_> = poseidon_lookups
+        .into_iter()
+        .map(|(left, right, domain, hash)| ([left, right], Fr::from(domain), hash))
+        .collect();
 
-        let key = account_key(proof.claim.address);
+    hash_traces.push((
+        [
+            Fr::from_u128(proof.address_high),
+            Fr::from_u128(u128::from(proof.address_low) << 96),
+        ],
+        HashDomain::Pair.into(),
+        proof.account_key,
+    ));
+
+    if let Some(data_hash) = proof.old.leaf_data_hash {
         hash_traces.push((
-            [
-                Fr::from_u128(address_high(proof.claim.address)),
-                Fr::from_u128(u128::from(address_low(proof.claim.address)) << 96),
-            ],
-            HashDomain::Pair.into(),
-            key,
+            [proof.old.key, data_hash],
+            HashDomain::Leaf.into(),
+            domain_hash(proof.old.key, data_hash, HashDomain::Leaf),
         ));
+    }
+    if let Some(data_hash) = proof.new.leaf_data_hash {
+        hash_traces.push((
+            [proof.new.key, data_hash],
+            HashDomain::Leaf.into(),
+            domain_hash(proof.new.key, data_hash, HashDomain::Leaf),
+        ));
+    }
 
-        if let Some(data_hash) = proof.old.leaf_data_hash {
-            hash_traces.push((
-                [proof.old.key, data_hash],
-                HashDomain::Leaf.into(),
-                domain_hash(proof.old.key, data_hash, HashDomain::Leaf),
-            ));
-        }
-        if let Some(data_hash) = proof.new.leaf_data_hash {
-            hash_traces.push((
-                [proof.new.key, data_hash],
-                HashDomain::Leaf.into(),
-                domain_hash(proof.new.key, data_hash, HashDomain::Leaf),
-            ));
-        }
-
-        for account_leaf_hash_traces in
-            [proof.old_account_hash_traces, proof.new_account_hash_traces]
-        {
-            for [left, right, digest] in account_leaf_hash_traces {
-                if domain_hash(left, right, HashDomain::AccountFields) == digest {
-                    hash_traces.push(([left, right], HashDomain::AccountFields.into(), digest))
-                } else if domain_hash(left, right, HashDomain::Leaf) == digest {
-                    hash_traces.push(([left, right], HashDomain::Leaf.into(), digest))
-                } else if domain_hash(left, right, HashDomain::Pair) == digest {
-                    hash_traces.push(([left, right], HashDomain::Pair.into(), digest))
-                }
+    for account_leaf_hash_traces in [proof.old_account_hash_traces, proof.new_account_hash_traces] {
+        for [left, right, digest] in account_leaf_hash_traces {
+            if domain_hash(left, right, HashDomain::AccountFields) == digest {
+                hash_traces.push(([left, right], HashDomain::AccountFields.into(), digest))
+            } else if domain_hash(left, right, HashDomain::Leaf) == digest {
+                hash_traces.push(([left, right], HashDomain::Leaf.into(), digest))
+            } else if domain_hash(left, right, HashDomain::Pair) == digest {
+                hash_traces.push(([left, right], HashDomain::Pair.into(), digest))
             }
         }
     }
-    hash_traces.sort();
-    hash_traces.dedup();
     hash_traces
 }
 
 /// ...
 pub fn key_bit_lookups(proofs: &[Proof]) -> Vec<(Fr, usize, bool)> {
-    let mut lookups = vec![(Fr::zero(), 0, false), (Fr::one(), 0, true)];
+    let mut lookups: DedupVec<_> = [(Fr::zero(), 0, false), (Fr::one(), 0, true)]
+        .into_iter()
+        .collect();
     for proof in proofs.iter() {
-        for (i, (direction, _, _, _, _, is_padding_open, is_padding_close)) in
-            proof.address_hash_traces.iter().rev().enumerate()
+        let key = proof.account_key;
+        for (
+            i,
+            AddressHashTrace {
+                direction,
+                is_padding_open,
+                is_padding_close,
+                ..
+            },
+        ) in proof.address_hash_traces.iter().rev().enumerate()
         {
             match (is_padding_open, is_padding_close) {
                 (false, false) => {
-                    let mut lookup_keys = vec![proof.old.key, proof.new.key];
-                    let key = account_key(proof.claim.address);
-                    if !lookup_keys.contains(&key) {
-                        lookup_keys.push(key);
-                    }
-                    lookup_keys
-                        .into_iter()
-                        .for_each(|k| lookups.push((k, i, *direction)));
+                    lookups.push((proof.old.key, i, *direction));
+                    lookups.push((proof.new.key, i, *direction));
+                    lookups.push((key, i, *direction));
                 }
                 (false, true) => {
                     lookups.push((proof.old.key, i, *direction));
@@ -2136,27 +2622,25 @@ pub fn key_bit_lookups(proofs: &[Proof]) -> Vec<(Fr, usize, bool)> {
                 (true, true) => unreachable!(),
             };
         }
-        lookups.extend(proof.storage.key_bit_lookups());
+        proof.storage.key_bit_lookups(&mut lookups);
     }
 
-    lookups.sort();
-    lookups.dedup();
-    lookups
+    lookups.into_sorted_vec()
 }
 
 /// ...
 pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>, Vec<Fr>) {
-    let mut u32s = vec![];
-    let mut u64s = vec![];
-    let mut u128s = vec![0];
-    let mut frs = vec![];
+    let mut u32s = DedupVec::default();
+    let mut u64s = DedupVec::default();
+    let mut u128s = DedupVec::default();
+    let mut frs = DedupVec::default();
+    u128s.push(0);
 
     for proof in proofs {
-        u128s.push(address_high(proof.claim.address));
-        u32s.push(address_low(proof.claim.address));
+        u128s.push(proof.address_high);
+        u32s.push(proof.address_low);
         match MPTProofType::from(proof.claim) {
             MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
-                u128s.push(address_high(proof.claim.address));
                 if let Some(account) = proof.old_account {
                     u64s.push(account.nonce);
                     u64s.push(account.code_size);
@@ -2167,7 +2651,6 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                 };
             }
             MPTProofType::BalanceChanged => {
-                u128s.push(address_high(proof.claim.address));
                 if let Some(account) = proof.old_account {
                     frs.push(account.balance);
                 };
@@ -2175,11 +2658,8 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                     frs.push(account.balance);
                 };
             }
-            MPTProofType::PoseidonCodeHashExists => {
-                u128s.push(address_high(proof.claim.address));
-            }
+            #[cfg(not(feature = "poseidon-codehash"))]
             MPTProofType::CodeHashExists => {
-                u128s.push(address_high(proof.claim.address));
                 if let Some(account) = proof.old_account {
                     let (hi, lo) = u256_hi_lo(&account.keccak_codehash);
                     u128s.push(hi);
@@ -2192,7 +2672,6 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                 };
             }
             MPTProofType::StorageChanged => {
-                u128s.push(address_high(proof.claim.address));
                 let (storage_key_high, storage_key_low) = u256_hi_lo(&proof.claim.storage_key());
                 u128s.push(storage_key_high);
                 u128s.push(storage_key_low);
@@ -2204,7 +2683,7 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                     } => {
                         let (old_value_high, old_value_low) = u256_hi_lo(&old_leaf.value());
                         let (new_value_high, new_value_low) = u256_hi_lo(&new_leaf.value());
-                        u128s.extend(vec![
+                        u128s.extend([
                             old_value_high,
                             old_value_low,
                             new_value_high,
@@ -2214,7 +2693,6 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
                 }
             }
             MPTProofType::StorageDoesNotExist => {
-                u128s.push(address_high(proof.claim.address));
                 let (storage_key_high, storage_key_low) = u256_hi_lo(&proof.claim.storage_key());
                 u128s.push(storage_key_high);
                 u128s.push(storage_key_low);
@@ -2223,33 +2701,24 @@ pub fn byte_representations(proofs: &[Proof]) -> (Vec<u32>, Vec<u64>, Vec<u128>,
         }
     }
 
-    u32s.sort();
-    u32s.dedup();
-
-    u64s.sort();
-    u64s.dedup();
-
-    u128s.sort();
-    u128s.dedup();
-
-    frs.sort();
-    frs.dedup();
-
-    (u32s, u64s, u128s, frs)
+    (
+        u32s.into_sorted_vec(),
+        u64s.into_sorted_vec(),
+        u128s.into_sorted_vec(),
+        frs.into_sorted_vec(),
+    )
 }
 
 /// ..
 pub fn mpt_update_keys(proofs: &[Proof]) -> Vec<Fr> {
-    let mut keys = vec![Fr::zero(), Fr::one()];
+    let mut keys: DedupVec<_> = [Fr::zero(), Fr::one()].into_iter().collect();
     for proof in proofs.iter() {
         keys.push(proof.old.key);
         keys.push(proof.new.key);
-        keys.push(account_key(proof.claim.address));
+        keys.push(proof.account_key);
         keys.extend(proof.storage.key_lookups());
         keys.push(proof.claim.old_root);
         keys.push(proof.claim.new_root);
     }
-    keys.sort();
-    keys.dedup();
-    keys
+    keys.into_sorted_vec()
 }
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
-use strum_macros::EnumIter;
+use std::{collections::HashMap, fmt};
+use strum_macros::{EnumIter, IntoStaticStr};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter, IntoStaticStr, Hash)]
 pub enum PathType {
     Start,        // Used as boundary marker between updates
     Common,       // Hashes for both the old and new path are being updated.
@@ -9,6 +9,12 @@ pub enum PathType {
     ExtensionNew, // The new path is being extended. The old hash doesn't change.
 }
 
+impl fmt::Display for PathType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.into())
+    }
+}
+
 const PATH_TRANSITIONS: [(PathType, PathType); 12] = [
     // Start -> Anything
     (PathType::Start, PathType::Start),
@@ -35,3 +41,13 @@ pub fn forward_transitions() -> HashMap<PathType, Vec<PathType>> {
     }
     map
 }
+
+// Allowed previous path types for each path type, which are implied by the forward transitions.
+#[cfg(feature = "audit")]
+pub fn backward_transitions() -> HashMap<PathType, Vec<PathType>> {
+    let mut map: HashMap<PathType, Vec<PathType>> = HashMap::new();
+    for (previous, current) in PATH_TRANSITIONS {
+        map.entry(current).or_default().push(previous)
+    }
+    map
+}
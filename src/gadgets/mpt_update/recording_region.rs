@@ -0,0 +1,120 @@
+use halo2_proofs::{
+    circuit::{layouter::RegionLayouter, Cell, RegionIndex, Value},
+    halo2curves::bn256::Fr,
+    plonk::{Advice, Any, Assigned, Column, Error, Fixed, Instance, Selector},
+};
+use std::collections::HashMap;
+
+// A region that records the names of its columns and the values assigned to its cells, instead
+// of assigning them in a circuit, so that the witness of an assignment function can be inspected
+// without running a prover.
+#[derive(Debug, Default)]
+pub struct RecordingRegion {
+    // In the order in which the columns were named.
+    names: Vec<(Column<Any>, String)>,
+    // The value is None if the cell was assigned an unknown value, e.g. a second phase cell
+    // assigned without a challenge.
+    cells: HashMap<(Column<Any>, usize), Option<Fr>>,
+}
+
+impl RecordingRegion {
+    pub fn named_columns(&self) -> &[(Column<Any>, String)] {
+        &self.names
+    }
+
+    // Returns None if the cell wasn't assigned, and Some(None) if it was assigned an unknown
+    // value.
+    pub fn cell(&self, column: impl Into<Column<Any>>, offset: usize) -> Option<Option<Fr>> {
+        self.cells.get(&(column.into(), offset)).copied()
+    }
+
+    fn record(
+        &mut self,
+        column: Column<Any>,
+        offset: usize,
+        value: Value<Assigned<Fr>>,
+    ) -> Result<Cell, Error> {
+        let mut evaluated = None;
+        value.map(|value| evaluated = Some(value.evaluate()));
+        self.cells.insert((column, offset), evaluated);
+        Ok(Cell {
+            region_index: RegionIndex::from(0),
+            row_offset: offset,
+            column,
+        })
+    }
+}
+
+impl RegionLayouter<Fr> for RecordingRegion {
+    fn enable_selector<'v>(
+        &'v mut self,
+        _: &'v (dyn Fn() -> String + 'v),
+        _: &Selector,
+        _: usize,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn name_column<'v>(
+        &'v mut self,
+        annotation: &'v (dyn Fn() -> String + 'v),
+        column: Column<Any>,
+    ) {
+        self.names.retain(|(named, _)| *named != column);
+        self.names.push((column, annotation()));
+    }
+
+    fn assign_advice<'v>(
+        &'v mut self,
+        _: &'v (dyn Fn() -> String + 'v),
+        column: Column<Advice>,
+        offset: usize,
+        to: &'v mut (dyn FnMut() -> Value<Assigned<Fr>> + 'v),
+    ) -> Result<Cell, Error> {
+        self.record(column.into(), offset, to())
+    }
+
+    fn assign_advice_from_constant<'v>(
+        &'v mut self,
+        _: &'v (dyn Fn() -> String + 'v),
+        column: Column<Advice>,
+        offset: usize,
+        constant: Assigned<Fr>,
+    ) -> Result<Cell, Error> {
+        self.record(column.into(), offset, Value::known(constant))
+    }
+
+    fn assign_advice_from_instance<'v>(
+        &mut self,
+        _: &'v (dyn Fn() -> String + 'v),
+        _: Column<Instance>,
+        _: usize,
+        _: Column<Advice>,
+        _: usize,
+    ) -> Result<(Cell, Value<Fr>), Error> {
+        // There are no instance columns outside of a circuit.
+        Err(Error::Synthesis)
+    }
+
+    fn instance_value(&mut self, _: Column<Instance>, _: usize) -> Result<Value<Fr>, Error> {
+        Err(Error::Synthesis)
+    }
+
+    fn assign_fixed<'v>(
+        &'v mut self,
+        _: &'v (dyn Fn() -> String + 'v),
+        column: Column<Fixed>,
+        offset: usize,
+        to: &'v mut (dyn FnMut() -> Value<Assigned<Fr>> + 'v),
+    ) -> Result<Cell, Error> {
+        self.record(column.into(), offset, to())
+    }
+
+    fn constrain_constant(&mut self, _: Cell, _: Assigned<Fr>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn constrain_equal(&mut self, _: Cell, _: Cell) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use crate::types::HashDomain;
 use crate::MPTProofType;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 use strum_macros::EnumIter;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash)]
@@ -15,6 +15,12 @@ pub enum SegmentType {
     StorageLeaf0,
 }
 
+impl fmt::Display for SegmentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 // Allowed transitions between current and next segment type, as a function of the proof type.
 pub fn transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>> {
     match proof {
@@ -136,8 +142,39 @@ pub fn transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>
             ),
         ]
         .into(),
-        MPTProofType::AccountDestructed => [].into(),
+        MPTProofType::AccountDestructed => [
+            (
+                SegmentType::Start,
+                vec![
+                    SegmentType::AccountTrie,  // mpt has > 1 account
+                    SegmentType::AccountLeaf0, // mpt has 1 account
+                ],
+            ),
+            (
+                SegmentType::AccountTrie,
+                vec![SegmentType::AccountTrie, SegmentType::AccountLeaf0],
+            ),
+            (SegmentType::AccountLeaf0, vec![SegmentType::Start]),
+        ]
+        .into(),
+    }
+}
+
+// Allowed previous segment types for each segment type other than Start, as a function of the
+// proof type.
+#[cfg(feature = "audit")]
+pub fn backward_transitions(proof: MPTProofType) -> HashMap<SegmentType, Vec<SegmentType>> {
+    let mut map: HashMap<SegmentType, Vec<SegmentType>> = HashMap::new();
+    for (previous, nexts) in transitions(proof) {
+        for current in nexts {
+            if current != SegmentType::Start {
+                map.entry(current).or_default().push(previous);
+            }
+        }
     }
+    // Sorted so that the constraints don't depend on the iteration order of the HashMap.
+    map.values_mut().for_each(|previous| previous.sort());
+    map
 }
 
 pub fn domains(segment_type: SegmentType) -> Vec<HashDomain> {
@@ -6,6 +6,7 @@ use crate::{
     },
     types::HashDomain,
     util::{rlc, u256_hi_lo},
+    Error,
 };
 use ethers_core::{k256::elliptic_curve::PrimeField, types::U256};
 use halo2_proofs::{
@@ -68,18 +69,18 @@ pub fn assign(
     [high_column, low_column]: [AdviceColumn; 2],
     [rlc_high, rlc_low]: [SecondPhaseAdviceColumn; 2],
     randomness: Value<Fr>,
-) {
+) -> Result<(), Error> {
     let (high, low) = u256_hi_lo(&word);
-    high_column.assign(region, offset, Fr::from_u128(high));
-    low_column.assign(region, offset, Fr::from_u128(low));
+    high_column.assign(region, offset, Fr::from_u128(high))?;
+    low_column.assign(region, offset, Fr::from_u128(low))?;
     rlc_high.assign(
         region,
         offset,
         randomness.map(|r| rlc(&high.to_be_bytes(), r)),
-    );
+    )?;
     rlc_low.assign(
         region,
         offset,
         randomness.map(|r| rlc(&low.to_be_bytes(), r)),
-    );
+    )
 }
@@ -1,6 +1,11 @@
 use crate::constraint_builder::{BinaryColumn, BinaryQuery, ConstraintBuilder, Query};
-use halo2_proofs::{circuit::Region, halo2curves::ff::FromUniformBytes, plonk::ConstraintSystem};
-use std::{cmp::Eq, collections::BTreeMap, hash::Hash};
+use crate::Error;
+use halo2_proofs::{
+    circuit::Region,
+    halo2curves::ff::FromUniformBytes,
+    plonk::{Advice, Column, ConstraintSystem},
+};
+use std::{cmp::Eq, collections::BTreeMap, fmt::Debug, hash::Hash};
 use strum::IntoEnumIterator;
 
 // One hot encoding for an enum with T::COUNT variants with COUNT - 1 binary columns.
@@ -33,10 +38,36 @@ impl<T: IntoEnumIterator + Hash + Eq + PartialOrd + Ord> OneHot<T> {
         region: &mut Region<'_, F>,
         offset: usize,
         value: T,
-    ) {
+    ) -> Result<(), Error> {
         if let Some(c) = self.columns.get(&value) {
-            c.assign(region, offset, true)
+            c.assign(region, offset, true)?;
         }
+        Ok(())
+    }
+
+    /// Names the binary column of each variant but the first, which has none.
+    pub fn name_columns<F: FromUniformBytes<64> + Ord>(
+        &self,
+        region: &mut Region<'_, F>,
+        name: &str,
+    ) where
+        T: Debug,
+    {
+        for (variant, column) in &self.columns {
+            column.name(region, &format!("{name} {variant:?}"));
+        }
+    }
+
+    /// Returns the variant whose binary column is set, given which columns are set in a
+    /// witness, e.g. one recorded outside of a circuit.
+    pub fn variant(&self, is_set: impl Fn(Column<Advice>) -> bool) -> T
+    where
+        T: Copy,
+    {
+        self.columns
+            .iter()
+            .find(|(_, column)| is_set(column.0))
+            .map_or_else(|| T::iter().next().unwrap(), |(variant, _)| *variant)
     }
 
     pub fn previous_matches<F: FromUniformBytes<64> + Ord>(&self, values: &[T]) -> BinaryQuery<F> {
@@ -52,36 +83,39 @@ impl<T: IntoEnumIterator + Hash + Eq + PartialOrd + Ord> OneHot<T> {
     }
 
     fn matches<F: FromUniformBytes<64> + Ord>(&self, values: &[T], r: i32) -> BinaryQuery<F> {
-        let query = values
+        // The first variant doesn't have a column, and matching it would need the sum of all
+        // columns, so when it is included, we match the complement of values instead, which
+        // gives a smaller expression.
+        let matches_first_variant = values.iter().any(|v| !self.columns.contains_key(v));
+        let query = self
+            .columns
             .iter()
-            .map(|v| {
-                self.columns
-                    .get(v)
-                    .map_or_else(|| !self.sum(r), |c| c.rotation(r))
-            })
-            .fold(Query::zero(), |a, b| a + b);
+            .filter(|(variant, _)| values.contains(*variant) != matches_first_variant)
+            .fold(Query::zero(), |a, (_, column)| a + column.rotation(r));
         // This cast is ok (if the values are distinct) because at most one column is set.
-        BinaryQuery(query)
+        if matches_first_variant {
+            !BinaryQuery(query)
+        } else {
+            BinaryQuery(query)
+        }
     }
 
     pub fn current<F: FromUniformBytes<64> + Ord>(&self) -> Query<F> {
-        T::iter().enumerate().fold(Query::zero(), |acc, (i, t)| {
-            acc + Query::from(u64::try_from(i).unwrap())
-                * self
-                    .columns
-                    .get(&t)
-                    .map_or_else(|| !self.sum(0), BinaryColumn::current)
-        })
+        self.value(0)
     }
 
     pub fn previous<F: FromUniformBytes<64> + Ord>(&self) -> Query<F> {
-        T::iter().enumerate().fold(Query::zero(), |acc, (i, t)| {
-            acc + Query::from(u64::try_from(i).unwrap())
-                * self
-                    .columns
-                    .get(&t)
-                    .map_or_else(|| !self.sum(-1), BinaryColumn::previous)
-        })
+        self.value(-1)
+    }
+
+    fn value<F: FromUniformBytes<64> + Ord>(&self, r: i32) -> Query<F> {
+        // The first variant has index 0, so it doesn't contribute to the sum.
+        T::iter()
+            .enumerate()
+            .filter_map(|(i, t)| self.columns.get(&t).map(|column| (i, column)))
+            .fold(Query::zero(), |acc, (i, column)| {
+                acc + Query::from(u64::try_from(i).unwrap()) * column.rotation(r)
+            })
     }
 
     fn sum<F: FromUniformBytes<64> + Ord>(&self, r: i32) -> BinaryQuery<F> {
@@ -1,11 +1,15 @@
 use crate::constraint_builder::{AdviceColumn, FixedColumn};
+#[cfg(any(test, feature = "test-util"))]
+use crate::Error;
 use halo2_proofs::plonk::{Advice, Column, Fixed};
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 use halo2_proofs::{circuit::Region, halo2curves::bn256::Fr, plonk::ConstraintSystem};
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 use hash_circuit::hash::Hashable;
+#[cfg(any(test, feature = "test-util"))]
+use rayon::prelude::*;
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 const MAX_POSEIDON_ROWS: usize = 200;
 
 /// Lookup  represent the poseidon table in zkevm circuit
@@ -20,7 +24,7 @@ pub trait PoseidonLookup {
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 #[derive(Clone, Copy)]
 pub struct PoseidonTable {
     q_enable: FixedColumn,
@@ -32,7 +36,7 @@ pub struct PoseidonTable {
     head_mark: AdviceColumn,
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 impl PoseidonTable {
     pub fn configure<F: halo2_proofs::halo2curves::ff::FromUniformBytes<64> + Ord>(
         cs: &mut ConstraintSystem<F>,
@@ -50,17 +54,34 @@ impl PoseidonTable {
         }
     }
 
-    pub fn load(&self, region: &mut Region<'_, Fr>, hash_traces: &[([Fr; 2], Fr, Fr)]) {
+    pub fn load(
+        &self,
+        region: &mut Region<'_, Fr>,
+        hash_traces: &[([Fr; 2], Fr, Fr)],
+    ) -> Result<(), Error> {
         // The test poseidon table starts assigning from the first row, which has a disabled
         // selector, but this is fine because the poseidon_lookup in the ConstraintBuilder
         // doesn't include the mpt circuit's selector column.
-        for (offset, hash_trace) in hash_traces.iter().enumerate() {
+        hash_traces.par_iter().for_each(|hash_trace| {
             assert!(
                 Hashable::hash_with_domain([hash_trace.0[0], hash_trace.0[1]], hash_trace.1)
                     == hash_trace.2,
                 "{:?}",
                 (hash_trace.0, hash_trace.1, hash_trace.2)
             );
+        });
+        for (column, name) in [
+            (self.left, "poseidon left"),
+            (self.right, "poseidon right"),
+            (self.hash, "poseidon hash"),
+            (self.control, "poseidon control"),
+            (self.domain_spec, "poseidon domain_spec"),
+            (self.head_mark, "poseidon head_mark"),
+        ] {
+            column.name(region, name);
+        }
+        self.q_enable.name(region, "poseidon q_enable");
+        for (offset, hash_trace) in hash_traces.iter().enumerate() {
             for (column, value) in [
                 (self.left, hash_trace.0[0]),
                 (self.right, hash_trace.0[1]),
@@ -69,20 +90,21 @@ impl PoseidonTable {
                 (self.domain_spec, hash_trace.1),
                 (self.head_mark, Fr::one()),
             ] {
-                column.assign(region, offset, value);
+                column.assign(region, offset, value)?;
             }
-            self.q_enable.assign(region, offset, Fr::one());
+            self.q_enable.assign(region, offset, Fr::one())?;
         }
 
         // We need to do this so that the fixed columns in the tests will not depend on the
         // number of poseidon hashes that are looked up.
         for offset in hash_traces.len()..MAX_POSEIDON_ROWS {
-            self.q_enable.assign(region, offset, Fr::one());
+            self.q_enable.assign(region, offset, Fr::one())?;
         }
+        Ok(())
     }
 }
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 impl PoseidonLookup for PoseidonTable {
     fn lookup_columns(&self) -> (FixedColumn, [AdviceColumn; 6]) {
         (
@@ -1,11 +1,14 @@
 #![allow(clippy::too_many_arguments)]
 #![deny(unsafe_code, unused_imports)]
 
-#[cfg(any(test, feature = "bench"))]
+#[cfg(any(test, feature = "test-util"))]
 mod circuit;
 pub mod constraint_builder;
+mod error;
 pub mod gadgets;
 mod mpt_table;
+pub mod prelude;
+mod profile;
 #[cfg(test)]
 mod tests;
 pub mod types;
@@ -14,9 +17,10 @@ mod util;
 pub mod mpt;
 pub mod serde;
 
+pub use error::Error;
 pub use gadgets::mpt_update::hash_traces;
 pub use mpt::MptCircuitConfig;
-pub use mpt_table::MPTProofType;
+pub use mpt_table::{MPTProofType, MptLookupRow, MPT_LOOKUP_ROW_BYTES, MPT_LOOKUP_ROW_VERSION};
 
-#[cfg(feature = "bench")]
-pub use circuit::TestCircuit;
+#[cfg(feature = "test-util")]
+pub use circuit::{TestCircuit, TestCircuit as MptTestCircuit};
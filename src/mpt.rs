@@ -1,5 +1,9 @@
+#[cfg(feature = "coverage")]
+use crate::constraint_builder::BinaryQuery;
+#[cfg(not(feature = "poseidon-codehash"))]
+use crate::gadgets::keccak::KeccakLookup;
 use crate::{
-    constraint_builder::{ConstraintBuilder, Query, SelectorColumn},
+    constraint_builder::{AdviceColumn, ConstraintBuilder, FixedColumn, Query, SelectorColumn},
     gadgets::{
         byte_bit::ByteBitGadget,
         byte_representation::ByteRepresentationConfig,
@@ -13,20 +17,34 @@ use crate::{
         rlc_randomness::RlcRandomness,
     },
     mpt_table::MPTProofType,
+    profile::profile,
     types::Proof,
+    Error,
 };
 use halo2_proofs::{
-    circuit::Layouter,
+    circuit::{Layouter, Region},
     halo2curves::{bn256::Fr, ff::FromUniformBytes},
-    plonk::{Challenge, ConstraintSystem, Error, Expression, VirtualCells},
+    plonk::{Challenge, ConstraintSystem, Expression, FirstPhase, VirtualCells},
 };
 use itertools::Itertools;
-use std::time::Instant;
+
+/// Lookup of the code hash and code size of accounts, for a bytecode circuit to check that the
+/// code it commits to matches the account leaf opened in the mpt circuit.
+pub trait AccountCodeLookup {
+    /// Expressions for q_enable, address, proof_type, and new_value of the mpt updates. Rows
+    /// with proof_type CodeHashExists have the rlc of the keccak code hash as new_value, and
+    /// rows with proof_type CodeSizeExists have the code size.
+    fn code_lookup_exprs<F: FromUniformBytes<64> + Ord>(
+        &self,
+        meta: &mut VirtualCells<'_, F>,
+    ) -> [Expression<F>; 4];
+}
 
 /// Config for MptCircuit
 #[derive(Clone)]
 pub struct MptCircuitConfig {
     selector: SelectorColumn,
+    is_first_row: SelectorColumn,
     is_final_row: SelectorColumn,
     rlc_randomness: RlcRandomness,
     mpt_update: MptUpdateConfig,
@@ -34,6 +52,8 @@ pub struct MptCircuitConfig {
     key_bit: KeyBitConfig,
     byte_bit: ByteBitGadget,
     byte_representation: ByteRepresentationConfig,
+    #[cfg(feature = "coverage")]
+    coverage_probes: Vec<(String, BinaryQuery<Fr>)>,
 }
 
 impl MptCircuitConfig {
@@ -42,6 +62,74 @@ impl MptCircuitConfig {
         evm_word_challenge: Challenge,
         poseidon: &impl PoseidonLookup,
     ) -> Self {
+        let (config, cb) = Self::configure_unbuilt(cs, evm_word_challenge, poseidon);
+        Self::build(config, cb, cs)
+    }
+
+    /// Like `configure`, but the new keccak code hash of every CodeHashExists proof is also
+    /// looked up in `keccak`, which ties it to the code hashed by the keccak circuit. Not
+    /// available with the poseidon-codehash feature, which has no keccak code hashes.
+    #[cfg(not(feature = "poseidon-codehash"))]
+    pub fn configure_with_keccak(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+        keccak: &impl KeccakLookup,
+    ) -> Self {
+        let (config, mut cb) = Self::configure_unbuilt(cs, evm_word_challenge, poseidon);
+        config.mpt_update.configure_keccak_lookup(&mut cb, keccak);
+        Self::build(config, cb, cs)
+    }
+
+    /// The mpt update config of a throwaway constraint system, for assigning proofs to a region
+    /// that isn't part of a circuit.
+    pub(crate) fn standalone_mpt_update() -> MptUpdateConfig {
+        let mut cs = ConstraintSystem::default();
+        let poseidon = UnassignedPoseidonTable(
+            FixedColumn(cs.fixed_column()),
+            [0; 6].map(|_| AdviceColumn(cs.advice_column())),
+        );
+        let challenge = cs.challenge_usable_after(FirstPhase);
+        Self::configure(&mut cs, challenge, &poseidon).mpt_update
+    }
+
+    /// Configures the circuit like `configure`, and returns a markdown specification of its
+    /// gates and lookups as well. Since the specification is generated by the code that adds
+    /// the constraints, it is always up to date.
+    pub fn configure_with_spec(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+    ) -> (Self, String) {
+        let (config, cb) = Self::configure_unbuilt(cs, evm_word_challenge, poseidon);
+        let spec = format!("# MPT circuit\n\n{}", cb.spec());
+        (Self::build(config, cb, cs), spec)
+    }
+
+    // Adds the gates and lookups of `cb` to `cs`. With the coverage feature, the conditions of
+    // the constraints and lookups are kept in the config as well.
+    fn build(config: Self, cb: ConstraintBuilder<Fr>, cs: &mut ConstraintSystem<Fr>) -> Self {
+        #[cfg(feature = "coverage")]
+        let config = Self {
+            coverage_probes: cb.coverage_probes(),
+            ..config
+        };
+        cb.build(cs);
+        config
+    }
+
+    /// The name and condition of every constraint and lookup of the circuit, for measuring
+    /// which of them a witness exercises.
+    #[cfg(feature = "coverage")]
+    pub fn coverage_probes(&self) -> &[(String, BinaryQuery<Fr>)] {
+        &self.coverage_probes
+    }
+
+    fn configure_unbuilt(
+        cs: &mut ConstraintSystem<Fr>,
+        evm_word_challenge: Challenge,
+        poseidon: &impl PoseidonLookup,
+    ) -> (Self, ConstraintBuilder<Fr>) {
         let selector = SelectorColumn(cs.fixed_column());
         let rlc_randomness = RlcRandomness(evm_word_challenge);
         let mut cb = ConstraintBuilder::new(selector);
@@ -71,6 +159,14 @@ impl MptCircuitConfig {
             &canonical_representation,
         );
 
+        // Constraints on rows that aren't Start rows can refer to the previous row. The first
+        // row is disabled and can have any witness, so the first enabled row must be a Start
+        // row, which doesn't depend on the row before it.
+        let is_first_row = SelectorColumn(cs.fixed_column());
+        cb.condition(is_first_row.current(), |cb| {
+            cb.assert("first mpt update row is Start", mpt_update.is_start());
+        });
+
         // This ensures that the final mpt update in the circuit is complete, since the padding
         // for the mpt update is a valid proof that shows the account with address 0 does not
         // exist in an mpt with root = 0 (i.e. the mpt is empty).
@@ -97,10 +193,9 @@ impl MptCircuitConfig {
             }
         });
 
-        cb.build(cs);
-
-        Self {
+        let config = Self {
             selector,
+            is_first_row,
             is_final_row,
             rlc_randomness,
             mpt_update,
@@ -108,7 +203,10 @@ impl MptCircuitConfig {
             byte_bit,
             canonical_representation,
             byte_representation,
-        }
+            #[cfg(feature = "coverage")]
+            coverage_probes: vec![],
+        };
+        (config, cb)
     }
 
     pub fn assign(
@@ -117,25 +215,38 @@ impl MptCircuitConfig {
         proofs: &[Proof],
         n_rows: usize,
     ) -> Result<(), Error> {
+        let n_rows_required = Self::n_rows_required(proofs);
+        if n_rows_required > n_rows {
+            return Err(Error::NotEnoughRows {
+                what: "mpt circuit",
+                required: n_rows_required,
+                available: n_rows,
+            });
+        }
+
         let randomness = self.rlc_randomness.value(layouter);
-        let (u32s, u64s, u128s, frs) = byte_representations(proofs);
+        let (u32s, u64s, u128s, frs) =
+            profile("byte representations", || byte_representations(proofs));
 
-        let mpt_updates_assign_dur = Instant::now();
         let use_par = std::env::var("PARALLEL_SYN").map_or(true, |s| s == *"true");
+        log::debug!("mpt updates assignment use_par = {}", use_par);
         if use_par {
-            let n_assigned_rows = self.mpt_update.assign_par(layouter, proofs, randomness);
+            let n_assigned_rows = profile("mpt updates assignment", || {
+                self.mpt_update.assign_par(layouter, proofs, randomness)
+            })?;
 
             layouter.assign_region(
                 || "mpt update padding rows",
                 |mut region| {
+                    self.mpt_update.name_columns(&mut region);
                     if n_assigned_rows == 0 {
                         // first row is all-zeroes row
                         for offset in 1..n_rows {
-                            self.mpt_update.assign_padding_row(&mut region, offset);
+                            self.mpt_update.assign_padding_row(&mut region, offset)?;
                         }
                     } else {
                         for offset in 0..(n_rows - (1 + n_assigned_rows)) {
-                            self.mpt_update.assign_padding_row(&mut region, offset);
+                            self.mpt_update.assign_padding_row(&mut region, offset)?;
                         }
                     }
                     Ok(())
@@ -145,86 +256,59 @@ impl MptCircuitConfig {
             layouter.assign_region(
                 || "mpt update",
                 |mut region| {
-                    let n_assigned_rows = self.mpt_update.assign(&mut region, proofs, randomness);
-
-                    assert!(
-                        2 + n_assigned_rows <= n_rows,
-                        "mpt circuit requires {n_assigned_rows} rows for mpt updates + 1 initial \
-                    all-zero row + at least 1 final padding row. Only {n_rows} rows available."
-                    );
+                    self.name_columns(&mut region);
+                    let n_assigned_rows = profile("mpt updates assignment", || {
+                        self.mpt_update.assign(&mut region, proofs, randomness)
+                    })?;
 
                     for offset in (1 + n_assigned_rows)..n_rows {
-                        self.mpt_update.assign_padding_row(&mut region, offset);
+                        self.mpt_update.assign_padding_row(&mut region, offset)?;
                     }
 
                     Ok(())
                 },
             )?;
         }
-        log::debug!(
-            "mpt updates assignment(use_par = {}) took {:?}",
-            use_par,
-            mpt_updates_assign_dur.elapsed()
-        );
 
+        let key_bit_lookups = profile("key bit lookups", || key_bit_lookups(proofs));
         if use_par {
-            let key_bit_time = {
-                let dur = Instant::now();
-                self.key_bit.assign_par(layouter, &key_bit_lookups(proofs));
-                dur.elapsed()
-            };
-            log::debug!("mpt key_bit assignment took {:?}", key_bit_time);
+            profile("key bit assignment", || {
+                self.key_bit.assign_par(layouter, &key_bit_lookups)
+            })?;
         }
 
         // pad canonical_representation to fixed count
         // notice each input cost 32 rows in canonical_representation, and inside
         // assign one extra input is added
-        let (keys, get_keys_time) = {
-            let dur = Instant::now();
-            let mut keys = mpt_update_keys(proofs);
-            keys.sort();
-            keys.dedup();
-            (keys, dur.elapsed())
-        };
-        let total_rep_size = n_rows / 32 - 1;
-        assert!(
-            total_rep_size >= keys.len(),
-            "no enough space for canonical representation of all keys (need {})",
-            keys.len()
-        );
-        log::debug!("get keys took {:?}", get_keys_time);
-
+        // mpt_update_keys are already sorted and deduplicated.
+        let keys = profile("mpt update keys", || mpt_update_keys(proofs));
         if use_par {
-            let canon_repr_time = {
-                let dur = Instant::now();
+            profile("canonical representation assignment", || {
                 self.canonical_representation
-                    .assign_par(layouter, randomness, &keys, n_rows);
-                dur.elapsed()
-            };
-            log::debug!("canonical_repr assignment took {:?}", canon_repr_time);
+                    .assign_par(layouter, randomness, &keys, n_rows)
+            })?;
         }
 
         layouter.assign_region(
             || "mpt keys",
             |mut region| {
+                self.name_columns(&mut region);
                 for offset in 1..n_rows {
-                    self.selector.enable(&mut region, offset);
+                    self.selector.enable(&mut region, offset)?;
                 }
 
-                let keys_assign_dur = Instant::now();
                 if !use_par {
-                    self.canonical_representation
-                        .assign(&mut region, randomness, &keys, n_rows);
-                    self.key_bit.assign(&mut region, &key_bit_lookups(proofs));
+                    profile("canonical representation assignment", || {
+                        self.canonical_representation
+                            .assign(&mut region, randomness, &keys, n_rows)
+                    })?;
+                    profile("key bit assignment", || {
+                        self.key_bit.assign(&mut region, &key_bit_lookups)
+                    })?;
                 }
 
-                let byte_bit_time = {
-                    let dur = Instant::now();
-                    self.byte_bit.assign(&mut region);
-                    dur.elapsed()
-                };
-                let byte_repr_time = {
-                    let dur = Instant::now();
+                profile("byte bit assignment", || self.byte_bit.assign(&mut region))?;
+                profile("byte representation assignment", || {
                     self.byte_representation.assign(
                         &mut region,
                         &u32s,
@@ -232,27 +316,35 @@ impl MptCircuitConfig {
                         &u128s,
                         &frs,
                         randomness,
-                    );
-                    dur.elapsed()
-                };
-                let keys_assign_time = keys_assign_dur.elapsed();
-                log::debug!("keys assignment took {:?}", keys_assign_time);
-                log::debug!(
-                    "byte_bit: {}",
-                    byte_bit_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
-                );
-                log::debug!(
-                    "byte_repr: {}",
-                    byte_repr_time.as_micros() as f64 / keys_assign_time.as_micros() as f64
-                );
-
-                self.is_final_row.enable(&mut region, n_rows - 1);
+                    )
+                })?;
+
+                self.is_first_row.enable(&mut region, 1)?;
+                self.is_final_row.enable(&mut region, n_rows - 1)?;
 
                 Ok(())
             },
-        )
+        )?;
+
+        Ok(())
     }
 
+    /// Names the columns of the circuit in `region`, so that MockProver failures and layout plots
+    /// refer to them by name instead of by index.
+    pub fn name_columns(&self, region: &mut Region<'_, Fr>) {
+        self.selector.name(region, "mpt selector");
+        self.is_first_row.name(region, "mpt is_first_row");
+        self.is_final_row.name(region, "mpt is_final_row");
+        self.mpt_update.name_columns(region);
+        self.canonical_representation.name_columns(region);
+        self.key_bit.name_columns(region);
+        self.byte_bit.name_columns(region);
+        self.byte_representation.name_columns(region);
+    }
+
+    /// Expressions for the columns of zkevm-circuits' MptTable, in its order: q_enable,
+    /// address, storage_key, proof_type, new_root, old_root, new_value, old_value. The values of
+    /// the last 7 for an mpt update are given by `MptUpdateConfig::lookup_values`.
     pub fn lookup_exprs<F: FromUniformBytes<64> + Ord>(
         &self,
         meta: &mut VirtualCells<'_, F>,
@@ -283,3 +375,23 @@ impl MptCircuitConfig {
         .unwrap()
     }
 }
+
+// Columns for the poseidon lookups of a throwaway constraint system, which are never assigned.
+struct UnassignedPoseidonTable(FixedColumn, [AdviceColumn; 6]);
+
+impl PoseidonLookup for UnassignedPoseidonTable {
+    fn lookup_columns(&self) -> (FixedColumn, [AdviceColumn; 6]) {
+        (self.0, self.1)
+    }
+}
+
+impl AccountCodeLookup for MptCircuitConfig {
+    fn code_lookup_exprs<F: FromUniformBytes<64> + Ord>(
+        &self,
+        meta: &mut VirtualCells<'_, F>,
+    ) -> [Expression<F>; 4] {
+        let [q_enable, address, _storage_key, proof_type, _new_root, _old_root, new_value, _old_value] =
+            self.lookup_exprs(meta);
+        [q_enable, address, proof_type, new_value]
+    }
+}
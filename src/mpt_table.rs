@@ -1,30 +1,73 @@
-use crate::types::{Claim, ClaimKind};
+use crate::{
+    types::{Claim, ClaimKind, Proof},
+    util::{address_high, address_low, rlc, u256_to_big_endian},
+    Error,
+};
+use ethers_core::types::{Address, U256};
+use halo2_proofs::{
+    circuit::Value,
+    halo2curves::{bn256::Fr, group::ff::PrimeField},
+};
 use serde::{Deserialize, Serialize};
-use strum_macros::EnumIter;
+use strum::IntoEnumIterator;
+use strum_macros::{EnumIter, IntoStaticStr};
 
-/// The defination is greped from state-circuit
+/// The defination is greped from state-circuit. The discriminants are the proof_type column
+/// values of the MptTable, so changing them changes `MPT_LOOKUP_ROW_VERSION`.
 #[derive(
-    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash, Serialize, Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    EnumIter,
+    IntoStaticStr,
+    Hash,
+    Serialize,
+    Deserialize,
 )]
 pub enum MPTProofType {
     /// nonce
-    NonceChanged,
+    NonceChanged = 0,
     /// balance
-    BalanceChanged,
+    BalanceChanged = 1,
     /// keccak codehash updated
-    CodeHashExists,
+    CodeHashExists = 2,
     /// poseidon codehash updated
-    PoseidonCodeHashExists,
+    PoseidonCodeHashExists = 3,
     /// code size updated
-    CodeSizeExists,
+    CodeSizeExists = 4,
     /// account is empty
-    AccountDoesNotExist,
+    AccountDoesNotExist = 5,
     /// storage
-    StorageChanged,
+    StorageChanged = 6,
     /// non exist proof for storage
-    StorageDoesNotExist,
+    StorageDoesNotExist = 7,
     /// account destructed
-    AccountDestructed,
+    AccountDestructed = 8,
+}
+
+impl TryFrom<u64> for MPTProofType {
+    type Error = Error;
+    fn try_from(x: u64) -> Result<Self, Self::Error> {
+        MPTProofType::iter()
+            .find(|proof_type| u64::from(*proof_type) == x)
+            .ok_or(Error::InvalidProofType(x))
+    }
+}
+
+impl From<MPTProofType> for u64 {
+    fn from(proof_type: MPTProofType) -> Self {
+        proof_type as u64
+    }
+}
+
+impl From<MPTProofType> for Fr {
+    fn from(proof_type: MPTProofType) -> Self {
+        Self::from(u64::from(proof_type))
+    }
 }
 
 impl From<Claim> for MPTProofType {
@@ -44,6 +87,235 @@ impl From<ClaimKind> for MPTProofType {
             ClaimKind::Storage { .. } => MPTProofType::StorageChanged,
             ClaimKind::IsEmpty(None) => MPTProofType::AccountDoesNotExist,
             ClaimKind::IsEmpty(Some(_)) => MPTProofType::StorageDoesNotExist,
+            ClaimKind::Destructed => MPTProofType::AccountDestructed,
+        }
+    }
+}
+
+/// Version of `MptLookupRow`. It is incremented whenever its fields, their encoding in the
+/// MptTable, or the discriminants of `MPTProofType` change.
+pub const MPT_LOOKUP_ROW_VERSION: u32 = 1;
+
+/// Length of the fixed layout encoding of `MptLookupRow`.
+pub const MPT_LOOKUP_ROW_BYTES: usize = 4 + 20 + 32 + 1 + 4 * 32;
+
+/// An mpt update as it appears in the MptTable, before its words are rlc'd. The fields are in
+/// the order of the table's columns after q_enable, and `values` gives the cells of the row, so
+/// a circuit looking up into the MptTable can build its side of the lookup from this type
+/// instead of repeating the column order and encodings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedMptLookupRow")]
+pub struct MptLookupRow {
+    pub version: u32,
+    pub address: Address,
+    pub storage_key: U256,
+    pub proof_type: MPTProofType,
+    pub new_root: U256,
+    pub old_root: U256,
+    /// Nonces and code sizes are integers, poseidon code hashes are the canonical value of a
+    /// field element, and the other values are 32 byte words.
+    pub new_value: U256,
+    pub old_value: U256,
+}
+
+// The fields of a deserialized MptLookupRow, before they are checked.
+#[derive(Deserialize)]
+struct UncheckedMptLookupRow {
+    version: u32,
+    address: Address,
+    storage_key: U256,
+    proof_type: MPTProofType,
+    new_root: U256,
+    old_root: U256,
+    new_value: U256,
+    old_value: U256,
+}
+
+impl TryFrom<UncheckedMptLookupRow> for MptLookupRow {
+    type Error = Error;
+    fn try_from(row: UncheckedMptLookupRow) -> Result<Self, Error> {
+        let row = Self {
+            version: row.version,
+            address: row.address,
+            storage_key: row.storage_key,
+            proof_type: row.proof_type,
+            new_root: row.new_root,
+            old_root: row.old_root,
+            new_value: row.new_value,
+            old_value: row.old_value,
+        };
+        row.check()?;
+        Ok(row)
+    }
+}
+
+impl MptLookupRow {
+    /// The cells of the row in the MptTable, after q_enable. Panics if the row has another
+    /// version or a value that isn't valid for its proof type, which rows that are decoded,
+    /// deserialized, or converted from a proof never have.
+    pub fn values(&self, randomness: Value<Fr>) -> [Value<Fr>; 7] {
+        assert_eq!(
+            self.version, MPT_LOOKUP_ROW_VERSION,
+            "unsupported mpt lookup row version"
+        );
+        let word_rlc = |x: U256| randomness.map(|r| rlc(&u256_to_big_endian(&x), r));
+        let value = |x: U256| match self.proof_type {
+            MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
+                Value::known(Fr::from(x.as_u64()))
+            }
+            MPTProofType::PoseidonCodeHashExists => {
+                Value::known(fr_from_u256(x).expect("poseidon code hash is not a field element"))
+            }
+            MPTProofType::BalanceChanged
+            | MPTProofType::CodeHashExists
+            | MPTProofType::StorageChanged => word_rlc(x),
+            MPTProofType::AccountDoesNotExist
+            | MPTProofType::StorageDoesNotExist
+            | MPTProofType::AccountDestructed => Value::known(Fr::zero()),
+        };
+        [
+            Value::known(
+                Fr::from_u128(address_high(self.address)) * Fr::from_u128(1 << 32)
+                    + Fr::from(u64::from(address_low(self.address))),
+            ),
+            word_rlc(self.storage_key),
+            Value::known(Fr::from(self.proof_type)),
+            word_rlc(self.new_root),
+            word_rlc(self.old_root),
+            value(self.new_value),
+            value(self.old_value),
+        ]
+    }
+
+    /// Encodes the row in a fixed layout, for consumers without a JSON dependency: the version
+    /// as 4 big-endian bytes, the 20 address bytes, the storage key, the proof type as 1 byte,
+    /// then the new root, old root, new value, and old value. Words are 32 big-endian bytes.
+    pub fn to_bytes(&self) -> [u8; MPT_LOOKUP_ROW_BYTES] {
+        let mut bytes = Vec::with_capacity(MPT_LOOKUP_ROW_BYTES);
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(self.address.as_bytes());
+        bytes.extend(u256_to_big_endian(&self.storage_key));
+        bytes.push(self.proof_type as u8);
+        for word in [self.new_root, self.old_root, self.new_value, self.old_value] {
+            bytes.extend(u256_to_big_endian(&word));
         }
+        bytes.try_into().unwrap()
     }
+
+    /// Decodes a row encoded by `to_bytes`, returning an error if it has another version, an
+    /// unknown proof type, or a value that isn't valid for its proof type.
+    pub fn from_bytes(bytes: &[u8; MPT_LOOKUP_ROW_BYTES]) -> Result<Self, Error> {
+        let version = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+        if version != MPT_LOOKUP_ROW_VERSION {
+            return Err(Error::UnsupportedLookupRowVersion(version));
+        }
+        let address = Address::from_slice(&bytes[4..24]);
+        let storage_key = U256::from_big_endian(&bytes[24..56]);
+        let proof_type = MPTProofType::try_from(u64::from(bytes[56]))?;
+        let [new_root, old_root, new_value, old_value] =
+            [0, 1, 2, 3].map(|i| U256::from_big_endian(&bytes[57 + 32 * i..89 + 32 * i]));
+        let row = Self {
+            version,
+            address,
+            storage_key,
+            proof_type,
+            new_root,
+            old_root,
+            new_value,
+            old_value,
+        };
+        row.check()?;
+        Ok(row)
+    }
+
+    // Checks the fields that `values` would panic on.
+    fn check(&self) -> Result<(), Error> {
+        if self.version != MPT_LOOKUP_ROW_VERSION {
+            return Err(Error::UnsupportedLookupRowVersion(self.version));
+        }
+        for value in [self.new_value, self.old_value] {
+            match self.proof_type {
+                MPTProofType::NonceChanged | MPTProofType::CodeSizeExists => {
+                    u64_value(self.proof_type, value)?;
+                }
+                MPTProofType::PoseidonCodeHashExists => {
+                    fr_value(self.proof_type, value)?;
+                }
+                MPTProofType::AccountDoesNotExist
+                | MPTProofType::StorageDoesNotExist
+                | MPTProofType::AccountDestructed
+                    if !value.is_zero() =>
+                {
+                    return Err(Error::InvalidValue {
+                        proof_type: self.proof_type,
+                        value,
+                    });
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&Proof> for MptLookupRow {
+    fn from(proof: &Proof) -> Self {
+        Self::from(proof.claim)
+    }
+}
+
+impl From<Claim> for MptLookupRow {
+    fn from(claim: Claim) -> Self {
+        let [old_value, new_value] = match claim.kind {
+            ClaimKind::Nonce { old, new } | ClaimKind::CodeSize { old, new } => {
+                [old, new].map(|x| U256::from(x.unwrap_or_default()))
+            }
+            ClaimKind::Balance { old, new } | ClaimKind::CodeHash { old, new } => {
+                [old, new].map(Option::unwrap_or_default)
+            }
+            ClaimKind::PoseidonCodeHash { old, new } => {
+                [old, new].map(|x| u256_from_fr(x.unwrap_or_default()))
+            }
+            ClaimKind::Storage {
+                old_value,
+                new_value,
+                ..
+            } => [old_value, new_value].map(Option::unwrap_or_default),
+            ClaimKind::IsEmpty(_) | ClaimKind::Destructed => [U256::zero(); 2],
+        };
+        Self {
+            version: MPT_LOOKUP_ROW_VERSION,
+            address: claim.address,
+            storage_key: claim.storage_key(),
+            proof_type: MPTProofType::from(claim),
+            new_root: u256_from_fr(claim.new_root),
+            old_root: u256_from_fr(claim.old_root),
+            new_value,
+            old_value,
+        }
+    }
+}
+
+pub(crate) fn u256_from_fr(x: Fr) -> U256 {
+    U256::from_little_endian(&x.to_bytes())
+}
+
+// Returns None if x isn't the canonical value of a field element.
+pub(crate) fn fr_from_u256(x: U256) -> Option<Fr> {
+    let mut bytes = [0; 32];
+    x.to_little_endian(&mut bytes);
+    Fr::from_bytes(&bytes).into()
+}
+
+// The nonce or code size of a row or encoded claim.
+pub(crate) fn u64_value(proof_type: MPTProofType, value: U256) -> Result<u64, Error> {
+    if value > U256::from(u64::MAX) {
+        return Err(Error::InvalidValue { proof_type, value });
+    }
+    Ok(value.as_u64())
+}
+
+// The poseidon code hash of a row or encoded claim.
+pub(crate) fn fr_value(proof_type: MPTProofType, value: U256) -> Result<Fr, Error> {
+    fr_from_u256(value).ok_or(Error::InvalidValue { proof_type, value })
 }
@@ -0,0 +1,11 @@
+//! The items needed to build the mpt circuit into a larger circuit and to witness it from traces.
+pub use crate::{
+    gadgets::{keccak::KeccakLookup, mpt_update::MptUpdateLookup, poseidon::PoseidonLookup},
+    mpt::{AccountCodeLookup, MptCircuitConfig},
+    serde::SMTTrace,
+    types::{batch::ProofBatch, Proof},
+    Error, MPTProofType, MptLookupRow,
+};
+
+#[cfg(feature = "test-util")]
+pub use crate::MptTestCircuit;
@@ -0,0 +1,24 @@
+//! Timing hooks for witness assignment, see the `profile` and `tracing` features.
+use std::fmt::Display;
+
+/// Runs `f` and logs how long it took: at info level under the `mpt_circuit::profile` target if
+/// the `profile` feature is enabled, and at debug level otherwise. If the `tracing` feature is
+/// enabled, `f` runs inside a `tracing` span with `label` as a field, so subscribers see the
+/// nesting of proofs, gadgets, and regions as well as their timings.
+#[inline]
+pub(crate) fn profile<T>(label: impl Display, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("mpt_circuit", label = %label).entered();
+    let start = std::time::Instant::now();
+    let result = f();
+    #[cfg(feature = "profile")]
+    log::info!(
+        target: "mpt_circuit::profile",
+        "{} took {:?}",
+        label,
+        start.elapsed()
+    );
+    #[cfg(not(feature = "profile"))]
+    log::debug!("{} took {:?}", label, start.elapsed());
+    result
+}
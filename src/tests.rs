@@ -1,17 +1,100 @@
-use crate::{circuit::TestCircuit, serde::SMTTrace, types::Proof, MPTProofType, MptCircuitConfig};
+#[cfg(not(feature = "poseidon-codehash"))]
+use crate::gadgets::keccak::KeccakTable;
+use crate::{
+    circuit::TestCircuit,
+    gadgets::{
+        mpt_update::{MptUpdateConfig, PathType},
+        poseidon::PoseidonTable,
+    },
+    hash_traces,
+    mpt::AccountCodeLookup,
+    serde::SMTTrace,
+    types::{
+        batch::ProofBatch,
+        storage::StorageProof,
+        trie::{TrieRow, TrieRows},
+        Bit, Claim, ClaimKind, Proof,
+    },
+    util::{account_key, fr, storage_key_hash, u256_from_hex},
+    MPTProofType, MptCircuitConfig, MptLookupRow, MPT_LOOKUP_ROW_VERSION,
+};
 use ethers_core::types::{Address, U256};
 use halo2_proofs::{
-    dev::MockProver,
+    circuit::{Layouter, SimpleFloorPlanner, Value},
+    dev::{FailureLocation, MockProver, VerifyFailure},
     halo2curves::bn256::{Bn256, Fr},
-    plonk::{keygen_vk, Circuit, ConstraintSystem},
-    poly::kzg::commitment::ParamsKZG,
+    plonk::{
+        keygen_vk, Advice, Challenge, Circuit, Column, ConstraintSystem, Error, FirstPhase, Fixed,
+        SecondPhase,
+    },
+    poly::{kzg::commitment::ParamsKZG, Rotation},
 };
+use itertools::Itertools;
 use mpt_zktrie::state::{builder::HASH_SCHEME_DONE, witness::WitnessGenerator, ZktrieState};
+use rand::Rng;
 use rand_chacha::rand_core::SeedableRng;
+use strum::IntoEnumIterator;
 
 const N_ROWS: usize = 8 * 256 + 1;
 const STORAGE_ADDRESS: Address = Address::repeat_byte(1);
 
+macro_rules! fixture {
+    ($proof_type:ident, $name:literal) => {
+        (
+            MPTProofType::$proof_type,
+            $name,
+            include_str!(concat!("traces/", $name)),
+        )
+    };
+}
+
+// Fixture traces of single updates, with their proof types and file names. Tests that run over
+// every fixture iterate over these, and other tests look traces up by file name.
+const FIXTURE_TRACES: [(MPTProofType, &str, &str); 17] = [
+    fixture!(AccountDoesNotExist, "empty_account_type_1.json"),
+    fixture!(AccountDoesNotExist, "empty_account_type_2.json"),
+    fixture!(BalanceChanged, "empty_account_type_1_balance_update.json"),
+    fixture!(BalanceChanged, "empty_account_type_2_balance_update.json"),
+    fixture!(BalanceChanged, "existing_account_balance_update.json"),
+    fixture!(NonceChanged, "empty_account_type_1_nonce_update.json"),
+    fixture!(NonceChanged, "empty_account_type_2_nonce_update.json"),
+    fixture!(NonceChanged, "existing_account_nonce_update.json"),
+    fixture!(CodeSizeExists, "existing_account_code_size_update.json"),
+    fixture!(
+        CodeHashExists,
+        "existing_account_keccak_codehash_update.json"
+    ),
+    fixture!(
+        PoseidonCodeHashExists,
+        "existing_account_poseidon_codehash_update.json"
+    ),
+    fixture!(StorageChanged, "existing_storage_update.json"),
+    fixture!(StorageChanged, "empty_storage_type_1_update_a.json"),
+    fixture!(StorageChanged, "empty_storage_type_1_update_b.json"),
+    fixture!(StorageChanged, "empty_storage_type_1_update_c.json"),
+    fixture!(StorageChanged, "empty_storage_type_2_update_a.json"),
+    fixture!(StorageChanged, "empty_storage_type_2_update_b.json"),
+];
+
+// Returns the proof type and trace of the fixture trace with the given file name.
+fn fixture_trace(name: &str) -> (MPTProofType, SMTTrace) {
+    let (proof_type, _, json) = FIXTURE_TRACES
+        .iter()
+        .find(|(_, file_name, _)| *file_name == name)
+        .unwrap_or_else(|| panic!("{name} is not a fixture trace"));
+    (*proof_type, serde_json::from_str(json).unwrap())
+}
+
+// Rng for randomly generated witnesses. The seed is fixed so runs are reproducible, and can be
+// changed with MPT_TEST_SEED to explore other cases or to replay one reported by a failing test.
+fn seeded_rng() -> rand_chacha::ChaCha20Rng {
+    let seed = std::env::var("MPT_TEST_SEED")
+        .map(|seed| seed.parse().expect("MPT_TEST_SEED is not a u64"))
+        .unwrap_or(3);
+    println!("MPT_TEST_SEED={seed}");
+    rand_chacha::ChaCha20Rng::seed_from_u64(seed)
+}
+
 fn initial_generator() -> WitnessGenerator {
     assert!(*HASH_SCHEME_DONE);
     let mut generator = WitnessGenerator::from(&ZktrieState::default());
@@ -54,9 +137,170 @@ fn reverse(trace: SMTTrace) -> SMTTrace {
 }
 
 fn mock_prove(witness: Vec<(MPTProofType, SMTTrace)>) {
-    let circuit = TestCircuit::new(N_ROWS, witness);
+    let proofs: Vec<Proof> = witness.into_iter().map(Proof::from).collect();
+    let circuit = TestCircuit::from_proofs(N_ROWS, proofs.clone());
     let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
-    assert_eq!(prover.verify(), Ok(()),);
+    let result = prover.verify();
+    if let Err(failures) = &result {
+        print_diagnosis(&proofs, failures);
+    }
+    assert_eq!(result, Ok(()),);
+}
+
+// Prints each failure along with the proof, segment, and path of the mpt update row it occurred
+// at. The offsets MockProver reports are rows of the mpt update region only if the witness is
+// assigned serially, so run failing tests with PARALLEL_SYN=false to get accurate descriptions.
+fn print_diagnosis(proofs: &[Proof], failures: &[VerifyFailure]) {
+    for failure in failures {
+        let location = match failure {
+            VerifyFailure::ConstraintNotSatisfied { location, .. }
+            | VerifyFailure::Lookup { location, .. } => location,
+            _ => {
+                println!("{failure}");
+                continue;
+            }
+        };
+        let offset = match location {
+            FailureLocation::InRegion { offset, .. } => *offset,
+            FailureLocation::OutsideRegion { row } => *row,
+        };
+        println!(
+            "{failure}\n    at {}",
+            MptUpdateConfig::describe_row(proofs, offset)
+        );
+    }
+}
+
+// Returns the verification failures for a witness that should be rejected.
+fn mock_prove_failures(proofs: Vec<Proof>) -> Vec<String> {
+    let circuit = TestCircuit::from_proofs(N_ROWS, proofs);
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    prover
+        .verify()
+        .expect_err("witness should not verify")
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+type Mutation = (String, Box<dyn Fn(&mut Proof)>);
+
+const TRIE_ROW_MUTATIONS: [(&str, fn(&mut TrieRow)); 4] = [
+    ("old hash", |row: &mut TrieRow| row.old += Fr::one()),
+    ("new hash", |row: &mut TrieRow| row.new += Fr::one()),
+    ("sibling", |row: &mut TrieRow| row.sibling += Fr::one()),
+    ("direction", |row: &mut TrieRow| {
+        row.direction = !row.direction
+    }),
+];
+
+fn trie_row_mutations(
+    name: &str,
+    n_rows: usize,
+    field_mutations: &[(&str, fn(&mut TrieRow))],
+    rows: fn(&mut Proof) -> &mut TrieRows,
+) -> Vec<Mutation> {
+    let mut mutations: Vec<Mutation> = vec![];
+    for i in 0..n_rows {
+        for (field, mutate) in field_mutations.iter().copied() {
+            mutations.push((
+                format!("{name} row {i} {field}"),
+                Box::new(move |proof: &mut Proof| mutate(&mut rows(proof).0[i])),
+            ));
+        }
+    }
+    mutations
+}
+
+// Every witness value of the proof that is changed by one of these mutations is constrained, so
+// each mutated proof must be rejected.
+fn mutations(proof: &Proof) -> Vec<Mutation> {
+    let mut mutations: Vec<Mutation> = vec![
+        (
+            "old root".to_string(),
+            Box::new(|proof: &mut Proof| proof.claim.old_root += Fr::one()),
+        ),
+        (
+            "new root".to_string(),
+            Box::new(|proof: &mut Proof| proof.claim.new_root += Fr::one()),
+        ),
+    ];
+    if matches!(
+        proof.claim.kind,
+        ClaimKind::Nonce { new: Some(_), .. }
+            | ClaimKind::Balance { new: Some(_), .. }
+            | ClaimKind::Storage {
+                new_value: Some(_),
+                ..
+            }
+    ) {
+        mutations.push((
+            "new value".to_string(),
+            Box::new(|proof: &mut Proof| match &mut proof.claim.kind {
+                ClaimKind::Nonce { new: Some(new), .. } => *new += 1,
+                ClaimKind::Balance { new: Some(new), .. } => *new += U256::one(),
+                ClaimKind::Storage {
+                    new_value: Some(new_value),
+                    ..
+                } => *new_value += U256::one(),
+                _ => unreachable!(),
+            }),
+        ));
+    }
+    // Indices into the account hash traces of the AccountLeaf2 and AccountLeaf3 siblings, which
+    // commit to the account fields that the proof doesn't change.
+    let account_leaf_siblings: &[(usize, usize)] = match proof.claim.kind {
+        ClaimKind::Nonce { .. } | ClaimKind::CodeSize { .. } => &[(3, 1), (2, 1)],
+        ClaimKind::Balance { .. } => &[(3, 1), (2, 0)],
+        ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => &[(3, 0), (1, 1)],
+        ClaimKind::CodeHash { .. } => &[(3, 0), (1, 0)],
+        ClaimKind::PoseidonCodeHash { .. } | ClaimKind::IsEmpty(None) | ClaimKind::Destructed => {
+            &[]
+        }
+    };
+    for (segment, (i, j)) in ["AccountLeaf2", "AccountLeaf3"]
+        .into_iter()
+        .zip(account_leaf_siblings.iter().copied())
+    {
+        mutations.push((
+            format!("{segment} sibling"),
+            Box::new(move |proof: &mut Proof| {
+                proof.old_account_hash_traces[i][j] += Fr::one();
+                proof.new_account_hash_traces[i][j] += Fr::one();
+            }),
+        ));
+    }
+    mutations.extend(trie_row_mutations(
+        "account trie",
+        proof.account_trie_rows.len(),
+        &TRIE_ROW_MUTATIONS,
+        |proof| &mut proof.account_trie_rows,
+    ));
+    if let StorageProof::Update { trie_rows, .. } = &proof.storage {
+        // The key bit lookups for storage trie rows are generated from their directions, so
+        // flipping a direction makes witness generation panic instead.
+        mutations.extend(trie_row_mutations(
+            "storage trie",
+            trie_rows.len(),
+            &TRIE_ROW_MUTATIONS[..3],
+            |proof| match &mut proof.storage {
+                StorageProof::Update { trie_rows, .. } => trie_rows,
+                StorageProof::Root(_) => unreachable!(),
+            },
+        ));
+    }
+    mutations
+}
+
+fn assert_mutations_rejected(proof: Proof) {
+    for (name, mutate) in mutations(&proof) {
+        let mut mutated_proof = proof.clone();
+        mutate(&mut mutated_proof);
+
+        let circuit = TestCircuit::from_proofs(N_ROWS, vec![mutated_proof]);
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert!(prover.verify().is_err(), "mutated {name} was not rejected");
+    }
 }
 
 #[test]
@@ -66,6 +310,77 @@ fn degree() {
     assert_eq!(meta.degree(), 9);
 }
 
+#[test]
+fn constraint_spec() {
+    let mut cs = ConstraintSystem::<Fr>::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (_, spec) = MptCircuitConfig::configure_with_spec(&mut cs, challenge, &poseidon);
+
+    assert!(spec.starts_with("# MPT circuit\n"));
+    assert!(spec.contains("## first mpt update row is Start\n\n- if `fixed["));
+    assert!(spec.contains("## lookups\n"));
+    assert!(spec.contains("- account mpt key = h(address_high, address_low << 96)\n"));
+}
+
+#[test]
+fn poseidon_codehash_feature_shape() {
+    let mut cs = ConstraintSystem::<Fr>::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    let (_, spec) = MptCircuitConfig::configure_with_spec(&mut cs, challenge, &poseidon);
+
+    assert_eq!(cs.degree(), 9);
+    assert_eq!(
+        spec.contains("keccak code hash is disabled"),
+        cfg!(feature = "poseidon-codehash")
+    );
+    assert!(!spec.contains("new keccak code hash is in keccak table"));
+}
+
+#[cfg(feature = "poseidon-codehash")]
+#[test]
+fn keccak_codehash_proofs_are_unsupported() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/existing_account_keccak_codehash_update.json"
+    ))
+    .unwrap();
+    let proof = Proof::from((MPTProofType::CodeHashExists, trace));
+    assert!(matches!(
+        MptUpdateConfig::layout_csv(&[proof], None, Value::unknown()),
+        Err(crate::Error::UnsupportedProofType(
+            MPTProofType::CodeHashExists
+        ))
+    ));
+}
+
+#[cfg(not(feature = "poseidon-codehash"))]
+#[test]
+fn keccak_lookup_shape() {
+    let mut cs = ConstraintSystem::<Fr>::default();
+    let poseidon = PoseidonTable::configure(&mut cs);
+    let challenge = cs.challenge_usable_after(FirstPhase);
+    MptCircuitConfig::configure(&mut cs, challenge, &poseidon);
+
+    let mut cs_with_keccak = ConstraintSystem::<Fr>::default();
+    let poseidon = PoseidonTable::configure(&mut cs_with_keccak);
+    let keccak = KeccakTable::configure(&mut cs_with_keccak);
+    let challenge = cs_with_keccak.challenge_usable_after(FirstPhase);
+    MptCircuitConfig::configure_with_keccak(&mut cs_with_keccak, challenge, &poseidon, &keccak);
+
+    // The keccak lookup adds the 3 columns of the keccak table and no columns of its own.
+    assert_eq!(cs_with_keccak.degree(), cs.degree());
+    assert_eq!(
+        cs_with_keccak.num_advice_columns(),
+        cs.num_advice_columns() + 2
+    );
+    assert_eq!(
+        cs_with_keccak.num_fixed_columns(),
+        cs.num_fixed_columns() + 1
+    );
+    assert_eq!(cs_with_keccak.lookups().len(), cs.lookups().len() + 1);
+}
+
 #[test]
 fn verifying_key_constant() {
     let params = ParamsKZG::<Bn256>::setup(17, rand_chacha::ChaCha20Rng::seed_from_u64(2));
@@ -374,6 +689,29 @@ fn existing_account_nonce_update() {
     mock_prove(vec![(MPTProofType::NonceChanged, trace)]);
 }
 
+#[test]
+fn max_nonce_and_code_size_updates() {
+    let mut generator = initial_generator();
+    let address = Address::repeat_byte(4);
+    let max = U256::from(u64::MAX);
+    for (zktrie_proof_type, proof_type) in [
+        (
+            mpt_zktrie::mpt_circuits::MPTProofType::NonceChanged,
+            MPTProofType::NonceChanged,
+        ),
+        (
+            mpt_zktrie::mpt_circuits::MPTProofType::CodeSizeExists,
+            MPTProofType::CodeSizeExists,
+        ),
+    ] {
+        let trace = generate_trace(&mut generator, zktrie_proof_type, address, max, None);
+        let proof = Proof::from((proof_type, trace.clone()));
+        proof.check();
+
+        mock_prove(vec![(proof_type, trace)]);
+    }
+}
+
 #[test]
 fn empty_account_type_1_nonce_update() {
     let mut generator = initial_generator();
@@ -1071,3 +1409,1432 @@ fn verify_benchmark_trace() {
     let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
     assert_eq!(prover.verify(), Ok(()));
 }
+
+#[test]
+fn extension_path_cannot_change_path_type() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_1_balance_update.json"
+    ))
+    .unwrap();
+    let proof = Proof::from((MPTProofType::BalanceChanged, trace));
+    assert_eq!(
+        proof.account_trie_rows.0.last().unwrap().path_type,
+        PathType::ExtensionNew
+    );
+
+    for path_type in [PathType::Common, PathType::ExtensionOld] {
+        let mut malformed_proof = proof.clone();
+        malformed_proof
+            .account_trie_rows
+            .0
+            .last_mut()
+            .unwrap()
+            .path_type = path_type;
+
+        let failures = mock_prove_failures(vec![malformed_proof]);
+        assert!(
+            failures
+                .iter()
+                .any(|failure| failure.contains("transition for path_type")),
+            "{failures:#?}"
+        );
+    }
+}
+
+#[test]
+fn nonexistence_proofs_cannot_change_root() {
+    let account_trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/empty_account_type_1.json")).unwrap();
+    let storage_trace = initial_storage_generator().handle_new_state(
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        STORAGE_ADDRESS,
+        U256::zero(),
+        U256::zero(),
+        Some(U256::from(3)),
+    );
+    let json = serde_json::to_string_pretty(&storage_trace).unwrap();
+    let storage_trace: SMTTrace = serde_json::from_str(&json).unwrap();
+
+    for (proof_type, trace) in [
+        (MPTProofType::AccountDoesNotExist, account_trace),
+        (MPTProofType::StorageDoesNotExist, storage_trace),
+    ] {
+        let mut proof = Proof::from((proof_type, trace));
+        proof.claim.new_root += Fr::one();
+
+        let failures = mock_prove_failures(vec![proof]);
+        assert!(
+            failures
+                .iter()
+                .any(|failure| failure.contains("hash doesn't change for empty account")),
+            "{failures:#?}"
+        );
+    }
+}
+
+#[cfg(feature = "audit")]
+#[test]
+fn audit_constraints_hold_for_fixture_traces() {
+    for (proof_type, _, json) in FIXTURE_TRACES {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        mock_prove(vec![(proof_type, trace)]);
+    }
+}
+
+#[test]
+fn mutated_witnesses_are_rejected() {
+    for name in [
+        "existing_account_balance_update.json",
+        "empty_account_type_1_balance_update.json",
+        "existing_storage_update.json",
+    ] {
+        assert_mutations_rejected(Proof::from(fixture_trace(name)));
+    }
+}
+
+#[test]
+fn random_updates_match_reference_trie() {
+    use mpt_zktrie::mpt_circuits::MPTProofType as ZktrieProofType;
+
+    let mut rng = seeded_rng();
+    let mut generator = initial_storage_generator();
+    let mut root = None;
+    for _ in 0..10 {
+        let mut proofs = vec![];
+        for _ in 0..30 {
+            // Addresses 1 to 9 already exist, the others may not.
+            let address = Address::repeat_byte(rng.gen_range(1..16));
+            let value = U256::from(rng.gen_range(1..u64::MAX));
+            let (proof_type, trace) = match rng.gen_range(0..3) {
+                0 => (
+                    MPTProofType::BalanceChanged,
+                    generate_trace(
+                        &mut generator,
+                        ZktrieProofType::BalanceChanged,
+                        address,
+                        value,
+                        None,
+                    ),
+                ),
+                1 => (
+                    MPTProofType::NonceChanged,
+                    generate_trace(
+                        &mut generator,
+                        ZktrieProofType::NonceChanged,
+                        address,
+                        value,
+                        None,
+                    ),
+                ),
+                _ => (
+                    MPTProofType::StorageChanged,
+                    generate_trace(
+                        &mut generator,
+                        ZktrieProofType::StorageChanged,
+                        STORAGE_ADDRESS,
+                        value,
+                        Some(U256::from(rng.gen_range(40..80))),
+                    ),
+                ),
+            };
+
+            let proof = Proof::from((proof_type, trace));
+            // The updates are applied to the reference trie in order, so each proof must start
+            // from the root the previous one ended at.
+            if let Some(root) = root {
+                assert_eq!(proof.claim.old_root, root);
+            }
+            root = Some(proof.claim.new_root);
+            proofs.push(proof);
+        }
+        assert!(MptCircuitConfig::n_rows_required(&proofs) <= N_ROWS);
+
+        let circuit = TestCircuit::from_proofs(N_ROWS, proofs.clone());
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        // Claiming any root, value, or storage key other than the ones of the reference trie, or
+        // opening the update with any other sibling, must be rejected.
+        let i = rng.gen_range(0..proofs.len());
+        let j = (i + 1) % proofs.len();
+        let other_new_root = proofs[j].claim.new_root;
+        let mut batch_mutations: Vec<Mutation> = vec![
+            (
+                "new root".to_string(),
+                Box::new(move |proof: &mut Proof| proof.claim.new_root = other_new_root),
+            ),
+            (
+                "storage key".to_string(),
+                Box::new(|proof: &mut Proof| proof.storage_key_bytes[31] ^= 1),
+            ),
+        ];
+        let (value_mutations, other_mutations): (Vec<_>, Vec<_>) = mutations(&proofs[i])
+            .into_iter()
+            .partition(|(name, _)| name == "new value");
+        let mut sibling_mutations: Vec<_> = other_mutations
+            .into_iter()
+            .filter(|(name, _)| name.ends_with("sibling"))
+            .collect();
+        batch_mutations.extend(value_mutations);
+        batch_mutations
+            .push(sibling_mutations.swap_remove(rng.gen_range(0..sibling_mutations.len())));
+
+        for (name, mutate) in batch_mutations {
+            let mut mutated_proofs = proofs.clone();
+            mutate(&mut mutated_proofs[i]);
+
+            let circuit = TestCircuit::from_proofs(N_ROWS, mutated_proofs);
+            let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+            assert!(
+                prover.verify().is_err(),
+                "mutated {name} of proof {i} was not rejected"
+            );
+        }
+    }
+}
+
+fn generate_trace(
+    generator: &mut WitnessGenerator,
+    proof_type: mpt_zktrie::mpt_circuits::MPTProofType,
+    address: Address,
+    new_value: U256,
+    key: Option<U256>,
+) -> SMTTrace {
+    let trace = generator.handle_new_state(proof_type, address, new_value, U256::zero(), key);
+    let json = serde_json::to_string_pretty(&trace).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn proof_type_and_path_type_matrix() {
+    use mpt_zktrie::mpt_circuits::MPTProofType as ZktrieProofType;
+
+    // An existing account, and accounts that don't exist with type 1 and type 2 paths.
+    let addresses = [
+        Address::repeat_byte(4),
+        Address::zero(),
+        Address::repeat_byte(20),
+    ];
+    for address in addresses {
+        for (zktrie_proof_type, proof_type) in [
+            (
+                ZktrieProofType::BalanceChanged,
+                MPTProofType::BalanceChanged,
+            ),
+            (ZktrieProofType::NonceChanged, MPTProofType::NonceChanged),
+            (
+                ZktrieProofType::CodeSizeExists,
+                MPTProofType::CodeSizeExists,
+            ),
+        ] {
+            let trace = generate_trace(
+                &mut initial_generator(),
+                zktrie_proof_type,
+                address,
+                U256::from(2342),
+                None,
+            );
+            mock_prove(vec![(proof_type, trace)]);
+        }
+    }
+    for address in &addresses[1..] {
+        let trace = generate_trace(
+            &mut initial_generator(),
+            ZktrieProofType::AccountDoesNotExist,
+            *address,
+            U256::zero(),
+            None,
+        );
+        mock_prove(vec![(MPTProofType::AccountDoesNotExist, trace)]);
+    }
+    for (zktrie_proof_type, proof_type, value) in [
+        (
+            ZktrieProofType::CodeSizeExists,
+            MPTProofType::CodeSizeExists,
+            U256::from(2342),
+        ),
+        (
+            ZktrieProofType::CodeHashExists,
+            MPTProofType::CodeHashExists,
+            U256([1111, u64::MAX, 444, 555]),
+        ),
+        (
+            ZktrieProofType::PoseidonCodeHashExists,
+            MPTProofType::PoseidonCodeHashExists,
+            U256::from(2342),
+        ),
+    ] {
+        let trace = generate_trace(
+            &mut initial_generator(),
+            zktrie_proof_type,
+            addresses[0],
+            value,
+            None,
+        );
+        mock_prove(vec![(proof_type, trace)]);
+    }
+
+    // An existing storage key, and storage keys that don't exist with type 1 and type 2 paths.
+    let storage_keys = [U256::from(45), U256::from(3), U256::from(500)];
+    for (i, storage_key) in storage_keys.into_iter().enumerate() {
+        let trace = generate_trace(
+            &mut initial_storage_generator(),
+            ZktrieProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::from(2342),
+            Some(storage_key),
+        );
+        mock_prove(vec![(MPTProofType::StorageChanged, trace)]);
+
+        // Setting an existing key to 0 deletes it, otherwise it is a non-existence proof.
+        let trace = generate_trace(
+            &mut initial_storage_generator(),
+            ZktrieProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::zero(),
+            Some(storage_key),
+        );
+        let proof_type = if i == 0 {
+            MPTProofType::StorageChanged
+        } else {
+            MPTProofType::StorageDoesNotExist
+        };
+        mock_prove(vec![(proof_type, trace)]);
+    }
+}
+
+#[cfg(feature = "coverage")]
+#[test]
+fn constraint_coverage() {
+    use halo2_proofs::dev::CellValue;
+    use std::collections::BTreeSet;
+
+    // Percentage of the constraints and lookups that the fixture and benchmark traces have to
+    // exercise. Raise it when adding tests for constraints that aren't exercised yet.
+    const MIN_COVERAGE_PERCENT: usize = 75;
+
+    let mut meta = ConstraintSystem::<Fr>::default();
+    let (_, config) = TestCircuit::configure(&mut meta);
+    let probes = config.coverage_probes();
+
+    let mut witness: Vec<(MPTProofType, SMTTrace)> = vec![];
+    for (proof_type, _, json) in FIXTURE_TRACES {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        if proof_type == MPTProofType::StorageChanged {
+            // Reversed storage insertions are deletions.
+            witness.push((proof_type, reverse(trace.clone())));
+        }
+        witness.push((proof_type, trace));
+    }
+    let benchmark_witness: Vec<(MPTProofType, SMTTrace)> =
+        serde_json::from_str(include_str!("../benches/traces.json")).unwrap();
+
+    // A constraint or lookup is exercised if its condition is true on a row of a valid witness.
+    let cell = |cells: &[Vec<CellValue<Fr>>], column: usize, row: usize, rotation: i32| {
+        let column = &cells[column];
+        match &column[(row as i32 + rotation).rem_euclid(column.len() as i32) as usize] {
+            CellValue::Assigned(value) => *value,
+            _ => Fr::zero(),
+        }
+    };
+    let mut exercised = BTreeSet::new();
+    for witness in [witness, benchmark_witness] {
+        let proofs: Vec<_> = witness.into_iter().map(Proof::from).collect();
+        let n_rows = MptCircuitConfig::n_rows_required(&proofs);
+        let circuit = TestCircuit::from_proofs(n_rows, proofs);
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert_eq!(prover.verify(), Ok(()));
+
+        for row in 0..prover.fixed()[0].len() {
+            for (i, (_, condition)) in probes.iter().enumerate() {
+                if exercised.contains(&i) {
+                    continue;
+                }
+                let value = condition.0.evaluate(
+                    &|column, rotation| cell(prover.advice(), column.index(), row, rotation),
+                    &|column, rotation| cell(prover.fixed(), column.index(), row, rotation),
+                    &|_| unreachable!("conditions don't depend on challenges"),
+                );
+                if value != Fr::zero() {
+                    exercised.insert(i);
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} of {} constraints exercised",
+        exercised.len(),
+        probes.len()
+    );
+    for (i, (name, _)) in probes.iter().enumerate() {
+        if !exercised.contains(&i) {
+            println!("not exercised: {name}");
+        }
+    }
+    assert!(
+        100 * exercised.len() >= MIN_COVERAGE_PERCENT * probes.len(),
+        "only {} of {} constraints exercised",
+        exercised.len(),
+        probes.len()
+    );
+}
+
+#[test]
+fn describe_rows() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap();
+    let proofs = vec![Proof::from((MPTProofType::StorageChanged, trace))];
+    let n_rows = proofs[0].n_rows();
+
+    assert_eq!(
+        MptUpdateConfig::describe_row(&proofs, 0),
+        "disabled first row"
+    );
+    assert!(MptUpdateConfig::describe_row(&proofs, 1)
+        .starts_with("proof 0 row 0: Start segment, Start path, depth 0"));
+    assert!(MptUpdateConfig::describe_row(&proofs, 2)
+        .starts_with("proof 0 row 1: AccountTrie segment, Common path, depth 1"));
+    assert!(MptUpdateConfig::describe_row(&proofs, n_rows)
+        .starts_with(&format!("proof 0 row {}: StorageLeaf0 segment", n_rows - 1)));
+    assert_eq!(
+        MptUpdateConfig::describe_row(&proofs, n_rows + 1),
+        "padding row"
+    );
+}
+
+#[test]
+fn proof_ids_in_diagnostics() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap();
+    let proof = Proof::from_trace_with_id(MPTProofType::StorageChanged, trace, "tx 0x1234");
+    assert_eq!(proof.id.as_deref(), Some("tx 0x1234"));
+    assert!(proof.to_string().starts_with("tx 0x1234\n"));
+
+    let proofs = vec![proof];
+    assert!(MptUpdateConfig::describe_row(&proofs, 1)
+        .starts_with("proof 0 (tx 0x1234) row 0: Start segment"));
+}
+
+#[test]
+fn account_path_types() {
+    for (name, path_types) in [
+        ("empty_account_type_1.json", [1, 1]),
+        ("empty_account_type_2.json", [2, 2]),
+        ("empty_account_type_2_nonce_update.json", [2, 0]),
+        ("existing_account_balance_update.json", [0, 0]),
+    ] {
+        let proof = Proof::from(fixture_trace(name));
+        assert_eq!(proof.account_path_types(), path_types);
+    }
+}
+
+#[test]
+fn display_proof() {
+    let trace: SMTTrace = serde_json::from_str(include_str!(
+        "traces/empty_account_type_2_nonce_update.json"
+    ))
+    .unwrap();
+    let proof = Proof::from((MPTProofType::NonceChanged, trace));
+
+    let display = proof.to_string();
+    assert!(display.starts_with(&proof.claim.to_string()));
+    assert_eq!(display.lines().count(), 3 + proof.address_hash_traces.len());
+    assert!(display.contains(&format!("{:?}", proof.claim.new_root)));
+    let path_type = proof.address_hash_traces[0].path_type();
+    assert!(display.contains(&path_type.to_string()));
+    assert!(format!("{:?}", proof.address_hash_traces[0])
+        .contains(&format!("path_type: {path_type:?}")));
+}
+
+#[test]
+fn layout_csv() {
+    let proofs: Vec<_> = [
+        "existing_account_balance_update.json",
+        "existing_storage_update.json",
+    ]
+    .into_iter()
+    .map(|name| Proof::from(fixture_trace(name)))
+    .collect();
+
+    let csv = MptUpdateConfig::layout_csv(&proofs, None, Value::known(Fr::from(11))).unwrap();
+    let lines = csv.lines().collect_vec();
+    let header = lines[0].split(',').collect_vec();
+    assert_eq!(
+        header[..7],
+        [
+            "offset",
+            "proof",
+            "row",
+            "segment_type",
+            "path_type",
+            "depth",
+            "proof_type"
+        ]
+    );
+    assert_eq!(lines.len(), 1 + proofs[0].n_rows() + proofs[1].n_rows());
+    assert!(lines[1].starts_with("1,0,0,Start,Start,0,BalanceChanged,"));
+    let cells: std::collections::HashMap<_, _> =
+        header.iter().zip_eq(lines[1].split(',')).collect();
+    assert_eq!(
+        cells[&"mpt_update old_hash"],
+        format!("{:?}", proofs[0].claim.old_root)
+    );
+    assert_eq!(cells[&"mpt_update domain"], "512");
+    assert_eq!(cells[&"mpt_update segment_type AccountTrie"], "");
+
+    let csv = MptUpdateConfig::layout_csv(&proofs, Some(1), Value::unknown()).unwrap();
+    let lines = csv.lines().collect_vec();
+    assert_eq!(lines.len(), 1 + proofs[1].n_rows());
+    assert!(lines[1].starts_with(&format!(
+        "{},1,0,Start,Start,0,StorageChanged,",
+        1 + proofs[0].n_rows()
+    )));
+    let cells: std::collections::HashMap<_, _> =
+        header.iter().zip_eq(lines[2].split(',')).collect();
+    assert_eq!(cells[&"mpt_update segment_type AccountTrie"], "1");
+    assert_eq!(cells[&"mpt_update depth"], "1");
+    assert_eq!(cells[&"mpt_update old_value"], "?");
+
+    let [n_rows_0, n_rows_1] = [0, 1].map(|i| proofs[i].n_rows());
+    assert_eq!(
+        MptUpdateConfig::proof_rows(&proofs),
+        [1..1 + n_rows_0, 1 + n_rows_0..1 + n_rows_0 + n_rows_1]
+    );
+    assert_eq!(
+        1 + n_rows_0 + n_rows_1,
+        MptUpdateConfig::n_rows_required(&proofs)
+    );
+}
+
+// The fixture traces were generated by mpt-zktrie, which wraps the reference Go zktrie, so they
+// serve as golden vectors: this crate must compute the same account keys, storage keys, leaf
+// hashes, and roots from them.
+#[test]
+fn fixtures_match_zktrie_hashes() {
+    let block_traces: Vec<(MPTProofType, SMTTrace)> = serde_json::from_str(include_str!(
+        "traces/createNameRegistratorPerTxsNotEnoughGas_d0_g0_v0.json"
+    ))
+    .unwrap();
+    for (_, trace) in &block_traces {
+        check_zktrie_keys(trace);
+    }
+
+    for (proof_type, _, json) in FIXTURE_TRACES {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        check_zktrie_keys(&trace);
+
+        // Proof::check recomputes the leaf hashes and the roots from the trace's paths.
+        let proof = Proof::from((proof_type, trace.clone()));
+        proof.check();
+        assert_eq!(proof.claim.old_root, fr(trace.account_path[0].root));
+        assert_eq!(proof.claim.new_root, fr(trace.account_path[1].root));
+    }
+}
+
+fn check_zktrie_keys(trace: &SMTTrace) {
+    assert_eq!(
+        account_key(Address::from(trace.address.0)),
+        fr(trace.account_key)
+    );
+    if let (Some(state_key), Some(state_update)) = (trace.state_key, trace.state_update) {
+        for entry in state_update.into_iter().flatten() {
+            assert_eq!(storage_key_hash(u256_from_hex(entry.key)), fr(state_key));
+        }
+    }
+}
+
+// Inserting a storage key whose hash shares a long prefix with the hash of an existing key adds
+// several ExtensionNew storage trie rows, and deleting it removes several ExtensionOld rows. The
+// siblings of all but the last of these rows must be 0.
+#[test]
+fn deep_storage_extension_siblings() {
+    let existing_keys: Vec<Fr> = (40..60).map(|i| storage_key_hash(U256::from(i))).collect();
+    let shared_prefix = |a: Fr, b: Fr| (0..256).take_while(|i| a.bit(*i) == b.bit(*i)).count();
+    let key = (1000..)
+        .map(U256::from)
+        .find(|key| {
+            let hash = storage_key_hash(*key);
+            existing_keys
+                .iter()
+                .any(|existing_key| shared_prefix(hash, *existing_key) >= 16)
+        })
+        .unwrap();
+
+    let trace = generate_trace(
+        &mut initial_storage_generator(),
+        mpt_zktrie::mpt_circuits::MPTProofType::StorageChanged,
+        STORAGE_ADDRESS,
+        U256::from(5),
+        Some(key),
+    );
+    mock_prove(vec![(MPTProofType::StorageChanged, trace.clone())]);
+    mock_prove(vec![(MPTProofType::StorageChanged, reverse(trace.clone()))]);
+
+    for (trace, path_type, constraint) in [
+        (
+            trace.clone(),
+            PathType::ExtensionNew,
+            "sibling is zero for non-final new extension path segments",
+        ),
+        (
+            reverse(trace),
+            PathType::ExtensionOld,
+            "sibling is zero for non-final old extension path segments",
+        ),
+    ] {
+        let mut proof = Proof::from((MPTProofType::StorageChanged, trace));
+        let StorageProof::Update { trie_rows, .. } = &mut proof.storage else {
+            panic!("storage proof has no trie rows");
+        };
+        let extension_rows: Vec<_> = trie_rows
+            .0
+            .iter_mut()
+            .filter(|row| row.path_type == path_type)
+            .collect();
+        assert!(extension_rows.len() >= 2, "{path_type:?} path is too short");
+        for row in extension_rows.into_iter().rev().skip(1) {
+            assert_eq!(row.sibling, Fr::zero());
+            row.sibling = Fr::one();
+        }
+
+        let failures = mock_prove_failures(vec![proof]);
+        assert!(
+            failures.iter().any(|failure| failure.contains(constraint)),
+            "{failures:#?}"
+        );
+    }
+}
+
+// Returns two addresses whose account keys share a 24 bit prefix, so that the second one is
+// inserted into the account trie with a long ExtensionNew path below the first one.
+fn deep_account_pair() -> (Address, Address) {
+    let mut addresses_by_prefix = std::collections::HashMap::new();
+    for i in 1u64.. {
+        let address = Address::from_low_u64_be(i);
+        let key = account_key(address);
+        let prefix: u32 = (0..24).filter(|j| key.bit(*j)).map(|j| 1 << j).sum();
+        if let Some(other_address) = addresses_by_prefix.insert(prefix, address) {
+            return (other_address, address);
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn deep_account_trie() {
+    use mpt_zktrie::mpt_circuits::MPTProofType as ZktrieProofType;
+
+    let (shallow, deep) = deep_account_pair();
+    let mut generator = initial_generator();
+    for (address, zktrie_proof_type, proof_type) in [
+        (
+            shallow,
+            ZktrieProofType::BalanceChanged,
+            MPTProofType::BalanceChanged,
+        ),
+        (
+            deep,
+            ZktrieProofType::AccountDoesNotExist,
+            MPTProofType::AccountDoesNotExist,
+        ),
+        (
+            deep,
+            ZktrieProofType::BalanceChanged,
+            MPTProofType::BalanceChanged,
+        ),
+        (
+            shallow,
+            ZktrieProofType::NonceChanged,
+            MPTProofType::NonceChanged,
+        ),
+        (
+            deep,
+            ZktrieProofType::BalanceChanged,
+            MPTProofType::BalanceChanged,
+        ),
+    ] {
+        let value = if proof_type == MPTProofType::AccountDoesNotExist {
+            U256::zero()
+        } else {
+            U256::from(23)
+        };
+        let trace = generate_trace(&mut generator, zktrie_proof_type, address, value, None);
+        let proof = Proof::from((proof_type, trace.clone()));
+        proof.check();
+        if address == deep && proof_type != MPTProofType::AccountDoesNotExist {
+            assert!(
+                proof.account_trie_rows.len() >= 24,
+                "account trie is only {} levels deep",
+                proof.account_trie_rows.len()
+            );
+        }
+
+        mock_prove(vec![(proof_type, trace)]);
+    }
+}
+
+#[test]
+fn account_destructed() {
+    use mpt_zktrie::mpt_circuits::MPTProofType as ZktrieProofType;
+
+    // Deleting deep collapses the long path it shares with shallow, and deleting the remaining
+    // accounts one by one covers both type 1 and type 2 new paths, down to the empty trie.
+    let (shallow, deep) = deep_account_pair();
+    let mut generator = initial_generator();
+    for address in [shallow, deep] {
+        generate_trace(
+            &mut generator,
+            ZktrieProofType::BalanceChanged,
+            address,
+            U256::one(),
+            None,
+        );
+    }
+
+    let mut witness = vec![];
+    for address in [deep, shallow]
+        .into_iter()
+        .chain((1..10).map(Address::repeat_byte))
+    {
+        let trace = generate_trace(
+            &mut generator,
+            ZktrieProofType::AccountDestructed,
+            address,
+            U256::zero(),
+            None,
+        );
+        let proof = Proof::from((MPTProofType::AccountDestructed, trace.clone()));
+        assert_eq!(
+            proof.account_path_types()[0],
+            0,
+            "old account must exist before it is destructed"
+        );
+        if address == deep {
+            assert_eq!(proof.account_path_types()[1], 1);
+
+            // The branch nodes that only separated deep and shallow are removed, and shallow
+            // takes the place of the highest one, below the branch nodes it shares with the
+            // other accounts.
+            assert_eq!(proof.account_trie_rows.0[0].path_type, PathType::Common);
+            let removed_rows = proof
+                .account_trie_rows
+                .0
+                .iter()
+                .filter(|row| row.path_type == PathType::ExtensionOld)
+                .collect_vec();
+            assert!(
+                removed_rows.len() > 1,
+                "shallow only moved up {} levels",
+                removed_rows.len()
+            );
+            let (last, rest) = removed_rows.split_last().unwrap();
+            assert!(rest.iter().all(|row| row.sibling == Fr::zero()));
+            assert_eq!(last.sibling, last.new);
+        }
+        witness.push((MPTProofType::AccountDestructed, trace));
+    }
+    let (proof_type, trace) = witness.last().unwrap().clone();
+    assert_eq!(Proof::from((proof_type, trace)).claim.new_root, Fr::zero());
+
+    mock_prove(witness);
+}
+
+// Adversarial witness for stress tests and benchmarks: an account inserted with a path at least
+// 24 levels deep, the largest nonce and code size, the largest storage value, and storage writes
+// of 0, including one to a key that was inserted by the update right before it.
+fn stress_witness() -> Vec<(MPTProofType, SMTTrace)> {
+    use mpt_zktrie::mpt_circuits::MPTProofType as ZktrieProofType;
+
+    let (shallow, deep) = deep_account_pair();
+    let max_u64 = U256::from(u64::MAX);
+    let mut generator = initial_storage_generator();
+    [
+        (ZktrieProofType::BalanceChanged, shallow, U256::one(), None),
+        (ZktrieProofType::BalanceChanged, deep, U256::one(), None),
+        (ZktrieProofType::NonceChanged, deep, max_u64, None),
+        (ZktrieProofType::CodeSizeExists, deep, max_u64, None),
+        (
+            ZktrieProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::zero(),
+            Some(U256::from(41)),
+        ),
+        (
+            ZktrieProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::MAX,
+            Some(U256::from(100)),
+        ),
+        (
+            ZktrieProofType::StorageChanged,
+            STORAGE_ADDRESS,
+            U256::zero(),
+            Some(U256::from(100)),
+        ),
+    ]
+    .into_iter()
+    .map(|(zktrie_proof_type, address, value, key)| {
+        let proof_type = match zktrie_proof_type {
+            ZktrieProofType::BalanceChanged => MPTProofType::BalanceChanged,
+            ZktrieProofType::NonceChanged => MPTProofType::NonceChanged,
+            ZktrieProofType::CodeSizeExists => MPTProofType::CodeSizeExists,
+            _ => MPTProofType::StorageChanged,
+        };
+        let trace = generate_trace(&mut generator, zktrie_proof_type, address, value, key);
+        (proof_type, trace)
+    })
+    .collect()
+}
+
+// Set STRESS_TRACES_OUT to a path to write the stress witness there, e.g. as benchmark input.
+#[test]
+fn stress_witness_verifies() {
+    let witness = stress_witness();
+    let proofs: Vec<Proof> = witness.iter().cloned().map(Proof::from).collect();
+    for (proof, next_proof) in proofs.iter().tuple_windows() {
+        assert_eq!(proof.claim.new_root, next_proof.claim.old_root);
+    }
+    for proof in &proofs {
+        proof.check();
+    }
+    assert!(proofs[1].account_trie_rows.len() >= 24);
+
+    let n_rows_required = MptCircuitConfig::n_rows_required(&proofs);
+    let circuit = TestCircuit::from_proofs(n_rows_required, proofs);
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+
+    if let Ok(path) = std::env::var("STRESS_TRACES_OUT") {
+        std::fs::write(path, serde_json::to_string_pretty(&witness).unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn proof_batch_root_chaining() {
+    let mut proofs: Vec<Proof> = stress_witness().into_iter().map(Proof::from).collect();
+    let batch = ProofBatch::new(proofs.clone()).unwrap();
+    assert_eq!(batch.len(), proofs.len());
+    assert_eq!(
+        batch.n_rows_required(),
+        MptCircuitConfig::n_rows_required(&proofs)
+    );
+
+    proofs.swap(2, 3);
+    assert!(matches!(
+        ProofBatch::new(proofs.clone()),
+        Err(crate::Error::UnchainedRoots {
+            index: 2,
+            id: None,
+            ..
+        })
+    ));
+
+    proofs[2].id = Some("tx 0x1234".to_string());
+    let error = ProofBatch::new(proofs).unwrap_err();
+    assert!(matches!(
+        &error,
+        crate::Error::UnchainedRoots { index: 2, id: Some(id), .. } if id == "tx 0x1234"
+    ));
+    assert!(error
+        .to_string()
+        .starts_with("mpt update 2 (tx 0x1234) has old root"));
+}
+
+// A test circuit with the mpt circuit and the poseidon table, and a table or stand-in circuit
+// that is connected to the mpt circuit by lookups, which is configured and assigned by T.
+#[derive(Clone, Default)]
+struct LookupTestCircuit<T> {
+    proofs: Vec<Proof>,
+    lookups: T,
+}
+
+trait LookupTestCase: Clone + Default {
+    type Config: Clone;
+
+    // Configures the mpt circuit as well, since the lookups may need to be configured first.
+    fn configure(
+        cs: &mut ConstraintSystem<Fr>,
+        poseidon: &PoseidonTable,
+        challenge: Challenge,
+    ) -> (MptCircuitConfig, Self::Config);
+
+    fn assign(
+        &self,
+        config: &Self::Config,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        randomness: Value<Fr>,
+    ) -> Result<(), Error>;
+}
+
+impl<T: LookupTestCase> Circuit<Fr> for LookupTestCircuit<T> {
+    type Config = (PoseidonTable, MptCircuitConfig, T::Config, Challenge);
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(cs: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let poseidon = PoseidonTable::configure(cs);
+        let challenge = cs.challenge_usable_after(FirstPhase);
+        let (mpt_circuit_config, lookups_config) = T::configure(cs, &poseidon, challenge);
+        (poseidon, mpt_circuit_config, lookups_config, challenge)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        let (poseidon, mpt_circuit_config, lookups_config, challenge) = config;
+        mpt_circuit_config.assign(&mut layouter, &self.proofs, N_ROWS)?;
+        layouter.assign_region(
+            || "load poseidon table",
+            |mut region| {
+                poseidon.load(&mut region, &hash_traces(&self.proofs))?;
+                Ok(())
+            },
+        )?;
+
+        let randomness = layouter.get_challenge(challenge);
+        self.lookups
+            .assign(&lookups_config, &mut layouter, &self.proofs, randomness)
+    }
+}
+
+// A minimal stand-in for the state circuit: each of its enabled rows must appear in the mpt
+// circuit's MptTable lookup columns.
+#[derive(Clone, Default)]
+struct StateLookups {
+    // index of a proof and column whose looked up value is changed
+    tampered: Option<(usize, usize)>,
+}
+
+impl LookupTestCase for StateLookups {
+    type Config = (Column<Fixed>, [Column<Advice>; 7]);
+
+    fn configure(
+        cs: &mut ConstraintSystem<Fr>,
+        poseidon: &PoseidonTable,
+        challenge: Challenge,
+    ) -> (MptCircuitConfig, Self::Config) {
+        let mpt_circuit_config = MptCircuitConfig::configure(cs, challenge, poseidon);
+
+        let q_enable = cs.fixed_column();
+        let state_columns = [0; 7].map(|_| cs.advice_column_in(SecondPhase));
+        cs.lookup_any("state circuit mpt lookup", |meta| {
+            let q_enable = meta.query_fixed(q_enable, Rotation::cur());
+            let inputs = std::iter::once(q_enable.clone()).chain(
+                state_columns
+                    .map(|column| q_enable.clone() * meta.query_advice(column, Rotation::cur())),
+            );
+            inputs
+                .zip_eq(mpt_circuit_config.lookup_exprs(meta))
+                .collect()
+        });
+
+        (mpt_circuit_config, (q_enable, state_columns))
+    }
+
+    fn assign(
+        &self,
+        &(q_enable, state_columns): &Self::Config,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        randomness: Value<Fr>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "state circuit mpt lookups",
+            |mut region| {
+                for (i, proof) in proofs.iter().enumerate() {
+                    region.assign_fixed(|| "q_enable", q_enable, i, || Value::known(Fr::one()))?;
+                    let values = MptUpdateConfig::lookup_values(proof, randomness);
+                    for (j, (column, value)) in state_columns.into_iter().zip(values).enumerate() {
+                        let value = if self.tampered == Some((i, j)) {
+                            value.map(|value| value + Fr::one())
+                        } else {
+                            value
+                        };
+                        region.assign_advice(|| "state", column, i, || value)?;
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+#[test]
+fn state_circuit_lookups_into_mpt_table() {
+    let mut proofs = vec![];
+    for name in [
+        "existing_account_balance_update.json",
+        "empty_account_type_1_nonce_update.json",
+        "existing_account_keccak_codehash_update.json",
+        "existing_storage_update.json",
+        "empty_account_type_2.json",
+    ] {
+        proofs.push(Proof::from(fixture_trace(name)));
+    }
+
+    let circuit = LookupTestCircuit {
+        proofs: proofs.clone(),
+        lookups: StateLookups { tampered: None },
+    };
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+
+    for column in 0..7 {
+        let circuit = LookupTestCircuit {
+            proofs: proofs.clone(),
+            lookups: StateLookups {
+                tampered: Some((3, column)),
+            },
+        };
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert!(
+            prover.verify().is_err(),
+            "tampered lookup column {column} was found in the mpt table"
+        );
+    }
+}
+
+#[cfg(not(feature = "poseidon-codehash"))]
+#[derive(Clone, Default)]
+struct KeccakCodeHashLookups {
+    // whether the new code hashes of the proofs are loaded into the keccak table
+    load_code_hashes: bool,
+}
+
+#[cfg(not(feature = "poseidon-codehash"))]
+impl LookupTestCase for KeccakCodeHashLookups {
+    type Config = KeccakTable;
+
+    fn configure(
+        cs: &mut ConstraintSystem<Fr>,
+        poseidon: &PoseidonTable,
+        challenge: Challenge,
+    ) -> (MptCircuitConfig, Self::Config) {
+        let keccak = KeccakTable::configure(cs);
+        let mpt_circuit_config =
+            MptCircuitConfig::configure_with_keccak(cs, challenge, poseidon, &keccak);
+        (mpt_circuit_config, keccak)
+    }
+
+    fn assign(
+        &self,
+        keccak: &Self::Config,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        randomness: Value<Fr>,
+    ) -> Result<(), Error> {
+        let code_hashes: Vec<_> = if self.load_code_hashes {
+            proofs
+                .iter()
+                .filter(|proof| MPTProofType::from(proof.claim) == MPTProofType::CodeHashExists)
+                .map(|proof| MptUpdateConfig::lookup_values(proof, randomness)[5])
+                .collect()
+        } else {
+            vec![]
+        };
+        layouter.assign_region(
+            || "load keccak table",
+            |mut region| {
+                keccak.load(&mut region, &code_hashes)?;
+                Ok(())
+            },
+        )
+    }
+}
+
+#[cfg(not(feature = "poseidon-codehash"))]
+#[test]
+fn keccak_code_hash_lookup() {
+    let proofs: Vec<_> = [
+        "existing_account_keccak_codehash_update.json",
+        "existing_account_balance_update.json",
+    ]
+    .into_iter()
+    .map(|name| Proof::from(fixture_trace(name)))
+    .collect();
+
+    let circuit = LookupTestCircuit {
+        proofs: proofs.clone(),
+        lookups: KeccakCodeHashLookups {
+            load_code_hashes: true,
+        },
+    };
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+
+    let circuit = LookupTestCircuit {
+        proofs,
+        lookups: KeccakCodeHashLookups {
+            load_code_hashes: false,
+        },
+    };
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert!(prover.verify().is_err());
+}
+
+#[derive(Clone, Default)]
+struct BytecodeLookups {
+    // index of a proof whose looked up code hash or code size is changed
+    tampered: Option<usize>,
+}
+
+impl LookupTestCase for BytecodeLookups {
+    type Config = (Column<Fixed>, [Column<Advice>; 2], Column<Advice>);
+
+    fn configure(
+        cs: &mut ConstraintSystem<Fr>,
+        poseidon: &PoseidonTable,
+        challenge: Challenge,
+    ) -> (MptCircuitConfig, Self::Config) {
+        let mpt_circuit_config = MptCircuitConfig::configure(cs, challenge, poseidon);
+
+        let q_enable = cs.fixed_column();
+        let address_and_proof_type = [0; 2].map(|_| cs.advice_column());
+        let value = cs.advice_column_in(SecondPhase);
+        cs.lookup_any("bytecode circuit code lookup", |meta| {
+            let q_enable = meta.query_fixed(q_enable, Rotation::cur());
+            let inputs = std::iter::once(q_enable.clone()).chain(
+                address_and_proof_type
+                    .into_iter()
+                    .chain([value])
+                    .map(|column| q_enable.clone() * meta.query_advice(column, Rotation::cur())),
+            );
+            inputs
+                .zip_eq(mpt_circuit_config.code_lookup_exprs(meta))
+                .collect()
+        });
+
+        (
+            mpt_circuit_config,
+            (q_enable, address_and_proof_type, value),
+        )
+    }
+
+    fn assign(
+        &self,
+        &(q_enable, [address, proof_type], value): &Self::Config,
+        layouter: &mut impl Layouter<Fr>,
+        proofs: &[Proof],
+        randomness: Value<Fr>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "bytecode circuit code lookups",
+            |mut region| {
+                for (i, proof) in proofs.iter().enumerate() {
+                    let values = MptUpdateConfig::lookup_values(proof, randomness);
+                    let new_value = if self.tampered == Some(i) {
+                        values[5].map(|value| value + Fr::one())
+                    } else {
+                        values[5]
+                    };
+                    region.assign_fixed(|| "q_enable", q_enable, i, || Value::known(Fr::one()))?;
+                    region.assign_advice(|| "address", address, i, || values[0])?;
+                    region.assign_advice(|| "proof type", proof_type, i, || values[2])?;
+                    region.assign_advice(|| "value", value, i, || new_value)?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+#[test]
+fn bytecode_circuit_lookups_code_hash_and_size() {
+    let proofs: Vec<_> = [
+        "existing_account_keccak_codehash_update.json",
+        "existing_account_code_size_update.json",
+    ]
+    .into_iter()
+    .map(|name| Proof::from(fixture_trace(name)))
+    .collect();
+
+    let circuit = LookupTestCircuit {
+        proofs: proofs.clone(),
+        lookups: BytecodeLookups { tampered: None },
+    };
+    let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+    assert_eq!(prover.verify(), Ok(()));
+
+    for tampered in 0..proofs.len() {
+        let circuit = LookupTestCircuit {
+            proofs: proofs.clone(),
+            lookups: BytecodeLookups {
+                tampered: Some(tampered),
+            },
+        };
+        let prover = MockProver::<Fr>::run(14, &circuit, vec![]).unwrap();
+        assert!(
+            prover.verify().is_err(),
+            "tampered code lookup {tampered} was found in the mpt table"
+        );
+    }
+}
+
+#[test]
+fn invalid_lookup_rows_and_claims_are_errors() {
+    let (proof_type, trace) = fixture_trace("existing_account_nonce_update.json");
+    let proof = Proof::from((proof_type, trace));
+    let row = MptLookupRow::from(&proof);
+
+    let mut bytes = row.to_bytes();
+    bytes[3] += 1;
+    assert!(matches!(
+        MptLookupRow::from_bytes(&bytes),
+        Err(crate::Error::UnsupportedLookupRowVersion(2))
+    ));
+
+    let mut bytes = row.to_bytes();
+    bytes[56] = 9;
+    assert!(matches!(
+        MptLookupRow::from_bytes(&bytes),
+        Err(crate::Error::InvalidProofType(9))
+    ));
+
+    // A nonce above 2^64.
+    let mut bytes = row.to_bytes();
+    bytes[57 + 2 * 32 + 23] = 1;
+    assert!(matches!(
+        MptLookupRow::from_bytes(&bytes),
+        Err(crate::Error::InvalidValue {
+            proof_type: MPTProofType::NonceChanged,
+            ..
+        })
+    ));
+
+    // A poseidon code hash that isn't a field element.
+    let mut bytes = row.to_bytes();
+    bytes[56] = MPTProofType::PoseidonCodeHashExists as u8;
+    bytes[57 + 2 * 32..57 + 3 * 32].fill(0xff);
+    assert!(matches!(
+        MptLookupRow::from_bytes(&bytes),
+        Err(crate::Error::InvalidValue {
+            proof_type: MPTProofType::PoseidonCodeHashExists,
+            ..
+        })
+    ));
+
+    let json = serde_json::to_string(&MptLookupRow {
+        version: MPT_LOOKUP_ROW_VERSION + 1,
+        ..row
+    })
+    .unwrap();
+    assert!(serde_json::from_str::<MptLookupRow>(&json).is_err());
+
+    let mut bytes = proof.claim.to_bytes();
+    bytes[..32].fill(0xff);
+    assert!(matches!(
+        Claim::from_bytes(&bytes),
+        Err(crate::Error::InvalidClaimEncoding(_))
+    ));
+
+    // A storage key for an account claim.
+    let mut bytes = proof.claim.to_bytes();
+    bytes[85] = 1;
+    assert!(matches!(
+        Claim::from_bytes(&bytes),
+        Err(crate::Error::InvalidClaimEncoding(_))
+    ));
+
+    // A new value that doesn't exist, but isn't 0.
+    let mut bytes = proof.claim.to_bytes();
+    bytes[117] &= 1;
+    assert!(matches!(
+        Claim::from_bytes(&bytes),
+        Err(crate::Error::InvalidClaimEncoding(_))
+    ));
+}
+
+#[test]
+fn mpt_lookup_row_round_trip() {
+    assert_eq!(
+        MPTProofType::iter()
+            .map(|proof_type| proof_type as u64)
+            .collect_vec(),
+        (0..9).collect_vec(),
+        "proof type discriminants changed without a new MPT_LOOKUP_ROW_VERSION"
+    );
+    for proof_type in MPTProofType::iter() {
+        assert_eq!(
+            MPTProofType::try_from(u64::from(proof_type)).unwrap(),
+            proof_type
+        );
+    }
+    assert!(matches!(
+        MPTProofType::try_from(9),
+        Err(crate::Error::InvalidProofType(9))
+    ));
+
+    let randomness = Fr::from(0x1234567);
+    for name in [
+        "existing_account_nonce_update.json",
+        "existing_account_balance_update.json",
+        "existing_account_keccak_codehash_update.json",
+        "existing_account_poseidon_codehash_update.json",
+        "existing_account_code_size_update.json",
+        "empty_account_type_2.json",
+        "existing_storage_update.json",
+    ] {
+        let (proof_type, trace) = fixture_trace(name);
+        let proof = Proof::from((proof_type, trace));
+
+        let row = MptLookupRow::from(&proof);
+        assert_eq!(row.version, MPT_LOOKUP_ROW_VERSION);
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(serde_json::from_str::<MptLookupRow>(&json).unwrap(), row);
+        assert_eq!(MptLookupRow::from_bytes(&row.to_bytes()).unwrap(), row);
+        assert_eq!(
+            Claim::from_bytes(&proof.claim.to_bytes()).unwrap(),
+            proof.claim
+        );
+
+        let [_, _, proof_type_value, _, _, new_value, old_value] =
+            row.values(Value::known(randomness));
+        let claim = proof.claim;
+        proof_type_value.assert_if_known(|x| *x == Fr::from(proof_type as u64));
+        new_value.assert_if_known(|x| *x == claim.new_value_assignment(randomness));
+        old_value.assert_if_known(|x| *x == claim.old_value_assignment(randomness));
+    }
+}
+
+// Checks that deserializing and serializing the traces in `json` doesn't change any of their
+// keys, hashes, or paths.
+fn assert_traces_round_trip(json: &str, name: &str) {
+    let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+    let actual = if expected.is_array() {
+        let traces: Vec<(MPTProofType, SMTTrace)> = serde_json::from_str(json).unwrap();
+        serde_json::to_value(traces).unwrap()
+    } else {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        serde_json::to_value(trace).unwrap()
+    };
+    assert_eq!(actual, expected, "{name} changed in round trip");
+}
+
+#[test]
+fn fixtures_round_trip() {
+    for (name, json) in [
+        (
+            "createNameRegistratorPerTxsNotEnoughGas_d0_g0_v0.json",
+            include_str!("traces/createNameRegistratorPerTxsNotEnoughGas_d0_g0_v0.json"),
+        ),
+        (
+            "depth_1_type_1_storage.json",
+            include_str!("traces/depth_1_type_1_storage.json"),
+        ),
+        (
+            "insert_into_singleton_storage_trie.json",
+            include_str!("traces/insert_into_singleton_storage_trie.json"),
+        ),
+    ] {
+        assert_traces_round_trip(json, name);
+    }
+    for (_, name, json) in FIXTURE_TRACES {
+        assert_traces_round_trip(json, name);
+    }
+}
+
+#[cfg(feature = "golden-traces")]
+#[test]
+fn golden_traces_round_trip() {
+    let dir = std::env::var("GOLDEN_TRACES_DIR").expect("GOLDEN_TRACES_DIR is not set");
+    let mut n_files = 0;
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "json")
+        {
+            continue;
+        }
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert_traces_round_trip(&json, &path.display().to_string());
+        n_files += 1;
+    }
+    assert!(n_files > 0, "no json files in {dir}");
+}
+
+#[test]
+fn creating_account_with_code_hash_is_an_error() {
+    let mut trace = generate_trace(
+        &mut initial_generator(),
+        mpt_zktrie::mpt_circuits::MPTProofType::NonceChanged,
+        Address::zero(),
+        U256::from(2342),
+        None,
+    );
+    trace.account_update[1].as_mut().unwrap().nonce = 0;
+
+    assert!(matches!(
+        Proof::try_from_trace(MPTProofType::CodeHashExists, trace),
+        Err(crate::Error::UnsupportedAccountCreation {
+            proof_type: MPTProofType::CodeHashExists
+        })
+    ));
+}
+
+#[test]
+fn invalid_traces_are_errors() {
+    for (fixture_proof_type, _, json) in FIXTURE_TRACES {
+        let trace: SMTTrace = serde_json::from_str(json).unwrap();
+        assert!(Proof::try_from_trace(fixture_proof_type, trace.clone()).is_ok());
+        // Other proof types may or may not match the trace, but never panic.
+        for proof_type in MPTProofType::iter() {
+            let _ = Proof::try_from_trace(proof_type, trace.clone());
+        }
+    }
+
+    let (_, trace) = fixture_trace("existing_account_nonce_update.json");
+    assert!(matches!(
+        Proof::try_from_trace(MPTProofType::BalanceChanged, trace.clone()),
+        Err(crate::Error::ProofTypeMismatch(
+            MPTProofType::BalanceChanged
+        ))
+    ));
+
+    let mut not_a_field_element = trace.clone();
+    not_a_field_element.account_path[0].root.0 = [0xff; 32];
+    assert!(matches!(
+        Proof::try_from_trace(MPTProofType::NonceChanged, not_a_field_element),
+        Err(crate::Error::InvalidTrace(_))
+    ));
+
+    let mut wrong_sibling = trace.clone();
+    wrong_sibling.account_path[1].path[0].sibling.0[0] ^= 1;
+    assert!(matches!(
+        Proof::try_from_trace(MPTProofType::NonceChanged, wrong_sibling),
+        Err(crate::Error::InvalidTrace(_))
+    ));
+
+    let mut wrong_account_key = trace;
+    wrong_account_key.account_key.0[0] ^= 1;
+    assert!(matches!(
+        Proof::try_from_trace(MPTProofType::NonceChanged, wrong_account_key),
+        Err(crate::Error::InvalidTrace(_))
+    ));
+}
+
+#[test]
+fn not_enough_rows_is_an_error() {
+    let trace: SMTTrace =
+        serde_json::from_str(include_str!("traces/existing_storage_update.json")).unwrap();
+    let proof = Proof::from((MPTProofType::StorageChanged, trace));
+    let n_rows = MptCircuitConfig::n_rows_required(&[proof.clone()]);
+
+    // There's no row left for the final padding row.
+    let circuit = TestCircuit::from_proofs(n_rows - 1, vec![proof.clone()]);
+    assert!(MockProver::<Fr>::run(14, &circuit, vec![]).is_err());
+
+    // The mpt update rows fit, but the byte bit, key bit, and byte and canonical
+    // representation rows don't.
+    let n_mpt_update_rows = MptUpdateConfig::n_rows_required(&[proof.clone()]) + 1;
+    assert!(n_mpt_update_rows < n_rows);
+    let circuit = TestCircuit::from_proofs(n_mpt_update_rows, vec![proof]);
+    assert!(MockProver::<Fr>::run(14, &circuit, vec![]).is_err());
+}
@@ -1,11 +1,13 @@
 use crate::{
     gadgets::mpt_update::PathType,
+    mpt_table::{fr_from_u256, fr_value, u64_value},
     serde::{AccountData, HexBytes, SMTNode, SMTPath, SMTTrace},
     util::{
-        account_key, check_domain_consistency, domain_hash, fr_from_biguint, rlc,
-        u256_from_biguint, u256_from_hex, u256_to_big_endian,
+        account_key, address_high, address_low, check_domain_consistency, domain_hash, ensure,
+        fr_from_biguint, pack_nonce_and_code_size, rlc, u256_from_biguint, u256_from_hex,
+        u256_to_big_endian, PanicContext,
     },
-    MPTProofType,
+    Error, MPTProofType, MptLookupRow,
 };
 use ethers_core::{
     k256::elliptic_curve::PrimeField,
@@ -15,11 +17,13 @@ use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
 use num_bigint::BigUint;
 use num_traits::identities::Zero;
+use std::fmt::{self, Debug, Display, Formatter};
 
+pub mod batch;
 pub mod storage;
 pub mod trie;
 use storage::StorageProof;
-use trie::TrieRows;
+use trie::{ensure_insertion_domain, next_domain, node_domain, TrieRows};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HashDomain {
@@ -72,7 +76,10 @@ impl HashDomain {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Length of the fixed layout encoding of `Claim`.
+pub const CLAIM_BYTES: usize = 2 * 32 + 20 + 1 + 32 + 1 + 2 * 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Claim {
     pub old_root: Fr,
     pub new_root: Fr,
@@ -80,7 +87,7 @@ pub struct Claim {
     pub kind: ClaimKind,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ClaimKind {
     // TODO: remove Option's and represent type of old and new account elsewhere?
     Nonce {
@@ -109,6 +116,7 @@ pub enum ClaimKind {
         new_value: Option<U256>,
     },
     IsEmpty(Option<U256>),
+    Destructed,
 }
 
 impl Claim {
@@ -132,7 +140,7 @@ impl Claim {
                 &u256_to_big_endian(&old_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::IsEmpty(_) | ClaimKind::Destructed => Fr::zero(),
         }
     }
 
@@ -149,9 +157,110 @@ impl Claim {
                 &u256_to_big_endian(&new_value.unwrap_or_default()),
                 randomness,
             ),
-            ClaimKind::IsEmpty(_) => Fr::zero(),
+            ClaimKind::IsEmpty(_) | ClaimKind::Destructed => Fr::zero(),
         }
     }
+
+    /// Encodes the claim in a fixed layout, for consumers without a JSON dependency: the old and
+    /// new roots, the 20 address bytes, the proof type as 1 byte, the storage key, a byte with
+    /// bit 0 set if the old value exists and bit 1 set if the new value exists, then the old and
+    /// new values. Roots, keys, and values are 32 big-endian bytes, and the storage key and
+    /// values are encoded like in `MptLookupRow`, so they are 0 if the claim doesn't have them.
+    pub fn to_bytes(&self) -> [u8; CLAIM_BYTES] {
+        let row = MptLookupRow::from(*self);
+        let [old_exists, new_exists] = match self.kind {
+            ClaimKind::Nonce { old, new } | ClaimKind::CodeSize { old, new } => {
+                [old.is_some(), new.is_some()]
+            }
+            ClaimKind::Balance { old, new } | ClaimKind::CodeHash { old, new } => {
+                [old.is_some(), new.is_some()]
+            }
+            ClaimKind::PoseidonCodeHash { old, new } => [old.is_some(), new.is_some()],
+            ClaimKind::Storage {
+                old_value,
+                new_value,
+                ..
+            } => [old_value.is_some(), new_value.is_some()],
+            ClaimKind::IsEmpty(_) | ClaimKind::Destructed => [false; 2],
+        };
+
+        let mut bytes = Vec::with_capacity(CLAIM_BYTES);
+        bytes.extend(u256_to_big_endian(&row.old_root));
+        bytes.extend(u256_to_big_endian(&row.new_root));
+        bytes.extend_from_slice(self.address.as_bytes());
+        bytes.push(row.proof_type as u8);
+        bytes.extend(u256_to_big_endian(&row.storage_key));
+        bytes.push(u8::from(old_exists) | (u8::from(new_exists) << 1));
+        bytes.extend(u256_to_big_endian(&row.old_value));
+        bytes.extend(u256_to_big_endian(&row.new_value));
+        bytes.try_into().unwrap()
+    }
+
+    /// Decodes a claim encoded by `to_bytes`, returning an error if the bytes aren't the encoding
+    /// of any claim.
+    pub fn from_bytes(bytes: &[u8; CLAIM_BYTES]) -> Result<Self, Error> {
+        let word = |i: usize| U256::from_big_endian(&bytes[i..i + 32]);
+        let root = |i: usize| {
+            fr_from_u256(word(i)).ok_or(Error::InvalidClaimEncoding("root is not a field element"))
+        };
+        let proof_type = MPTProofType::try_from(u64::from(bytes[84]))?;
+        let key = word(85);
+        let existence = bytes[117];
+        if existence > 3 {
+            return Err(Error::InvalidClaimEncoding(
+                "existence byte has bits set other than 0 and 1",
+            ));
+        }
+        let value = |bit: u8, i: usize| match (existence & bit != 0, word(i)) {
+            (true, value) => Ok(Some(value)),
+            (false, value) if value.is_zero() => Ok(None),
+            (false, _) => Err(Error::InvalidClaimEncoding(
+                "value that doesn't exist is not 0",
+            )),
+        };
+        let (old, new) = (value(1, 118)?, value(2, 150)?);
+        let u64s = |x: Option<U256>| x.map(|x| u64_value(proof_type, x)).transpose();
+        let frs = |x: Option<U256>| x.map(|x| fr_value(proof_type, x)).transpose();
+
+        let kind = match proof_type {
+            MPTProofType::NonceChanged => ClaimKind::Nonce {
+                old: u64s(old)?,
+                new: u64s(new)?,
+            },
+            MPTProofType::BalanceChanged => ClaimKind::Balance { old, new },
+            MPTProofType::CodeHashExists => ClaimKind::CodeHash { old, new },
+            MPTProofType::PoseidonCodeHashExists => ClaimKind::PoseidonCodeHash {
+                old: frs(old)?,
+                new: frs(new)?,
+            },
+            MPTProofType::CodeSizeExists => ClaimKind::CodeSize {
+                old: u64s(old)?,
+                new: u64s(new)?,
+            },
+            MPTProofType::StorageChanged => ClaimKind::Storage {
+                key,
+                old_value: old,
+                new_value: new,
+            },
+            MPTProofType::AccountDoesNotExist => ClaimKind::IsEmpty(None),
+            MPTProofType::StorageDoesNotExist => ClaimKind::IsEmpty(Some(key)),
+            MPTProofType::AccountDestructed => ClaimKind::Destructed,
+        };
+        let claim = Self {
+            old_root: root(0)?,
+            new_root: root(32)?,
+            address: Address::from_slice(&bytes[64..84]),
+            kind,
+        };
+        // The storage key and values are only decoded for the proof types that have them, so
+        // they must be 0 for the others.
+        if claim.to_bytes() != *bytes {
+            return Err(Error::InvalidClaimEncoding(
+                "storage key or value set for a proof type without it",
+            ));
+        }
+        Ok(claim)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -163,8 +272,15 @@ struct LeafNode {
 #[derive(Clone, Debug)]
 pub struct Proof {
     pub claim: Claim,
-    // direction, open_hash_domain, close_hash_domain, open value, close value, sibling, is_padding_open, is_padding_close
-    pub address_hash_traces: Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)>,
+    // Opaque identifier given by the caller, e.g. a transaction hash or rw counter, which is
+    // only used in diagnostics.
+    pub id: Option<String>,
+    // Scalars derived from the claim, computed once here instead of during assignment.
+    pub account_key: Fr,
+    pub address_high: u128,
+    pub address_low: u32,
+    pub storage_key_bytes: [u8; 32], // big endian
+    pub address_hash_traces: Vec<AddressHashTrace>,
 
     // TODO: make this optional
     leafs: [Option<LeafNode>; 2],
@@ -206,6 +322,140 @@ impl From<AccountData> for EthAccount {
 }
 
 impl Proof {
+    /// Converts a trace like `Proof::from`, tagging the proof with `id`. The id is printed if the
+    /// trace is invalid, and in assignment panics and row descriptions for the proof.
+    pub fn from_trace_with_id(
+        proof_type: MPTProofType,
+        trace: SMTTrace,
+        id: impl Into<String>,
+    ) -> Self {
+        let id = id.into();
+        let _context = PanicContext(format!("converting the {proof_type:?} trace of {id}"));
+        Self {
+            id: Some(id),
+            ..Self::from((proof_type, trace))
+        }
+    }
+
+    /// Converts a trace into a proof, returning an error if the trace is invalid, isn't an update
+    /// of the proof type, or creates an account with an update that the circuit doesn't support.
+    pub fn try_from_trace(proof_type: MPTProofType, trace: SMTTrace) -> Result<Self, Error> {
+        check_trace_encoding(&trace)?;
+        let claim = Claim::try_from((&proof_type, &trace))?;
+
+        let storage = StorageProof::try_from(&trace)?;
+
+        let key = account_key(claim.address);
+        ensure(
+            key == fr(trace.account_key),
+            "account key isn't the hash of the address",
+        )?;
+
+        let account_trie_rows = TrieRows::new(
+            key,
+            &trace.account_path[0].path,
+            &trace.account_path[1].path,
+            trace.account_path[0].leaf,
+            trace.account_path[1].leaf,
+        )?;
+
+        let leafs = [0, 1].map(|i| get_leaf(&trace.account_path[i]));
+        let leaf_hashes = leafs.map(leaf_hash);
+        let address_hash_traces = get_internal_hash_traces(
+            key,
+            leaf_hashes,
+            &trace.account_path[0].path,
+            &trace.account_path[1].path,
+        )?;
+        check_hash_traces_new(&address_hash_traces)?;
+
+        let [old_account, new_account] = trace.account_update;
+        let old_account_hash_traces = match &old_account {
+            None => empty_account_hash_traces(leafs[0], leaf_hashes[0]),
+            Some(account) => account_hash_traces(key, account, storage.old_root()),
+        };
+        let new_account_hash_traces = match &new_account {
+            None => empty_account_hash_traces(leafs[1], leaf_hashes[1]),
+            Some(account) => account_hash_traces(key, account, storage.new_root()),
+        };
+        ensure(
+            old_account_hash_traces[5][2] == leaf_hashes[0]
+                && new_account_hash_traces[5][2] == leaf_hashes[1],
+            "account leaf doesn't hash to the leaf of the account path",
+        )?;
+
+        let [old, new] = trace.account_path.map(|path| {
+            // The account_key(address) if the account exists
+            // else: path.leaf.sibling if it's a type 1 non-existence proof
+            // otherwise account_key(address) if it's a type 2 non-existence proof
+            let key = path.leaf.map_or(key, |l| fr(l.sibling));
+
+            let leaf_data_hash = path.leaf.map(|leaf| fr(leaf.value));
+
+            Path {
+                key,
+                leaf_data_hash,
+            }
+        });
+
+        let old_account = match old_account {
+            Some(account_data) => {
+                let mut account = EthAccount::from(account_data);
+                account.storage_root = storage.old_root();
+                Some(account)
+            }
+            None => None,
+        };
+        let new_account = match new_account {
+            Some(account_data) => {
+                let mut account = EthAccount::from(account_data);
+                account.storage_root = storage.new_root();
+                Some(account)
+            }
+            None => None,
+        };
+
+        let mut storage_key_bytes = [0; 32];
+        claim.storage_key().to_big_endian(&mut storage_key_bytes);
+
+        Ok(Self {
+            claim,
+            id: None,
+            account_key: key,
+            address_high: address_high(claim.address),
+            address_low: address_low(claim.address),
+            storage_key_bytes,
+            address_hash_traces,
+            old_account_hash_traces,
+            new_account_hash_traces,
+            leafs,
+            storage,
+            old,
+            new,
+            old_account,
+            new_account,
+            account_trie_rows,
+        })
+    }
+
+    /// Names the proof in diagnostics by its index in the witness and its id, if any.
+    pub fn name(&self, index: usize) -> String {
+        match &self.id {
+            Some(id) => format!("proof {index} ({id})"),
+            None => format!("proof {index}"),
+        }
+    }
+
+    /// The types of the old and new account paths: 0 if the path ends at the leaf of the account,
+    /// 1 if it ends at the leaf of another account, and 2 if it ends at an empty node.
+    pub fn account_path_types(&self) -> [u8; 2] {
+        [&self.old, &self.new].map(|path| match path.leaf_data_hash {
+            None => 2,
+            Some(_) if path.key == self.account_key => 0,
+            Some(_) => 1,
+        })
+    }
+
     pub fn n_rows(&self) -> usize {
         if self.old_account.is_none() && self.new_account.is_none() {
             return 1 + self.address_hash_traces.len();
@@ -219,6 +469,7 @@ impl Proof {
                 ClaimKind::CodeHash { .. } => 4,
                 ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => 4,
                 ClaimKind::IsEmpty(None) => 0,
+                ClaimKind::Destructed => 1,
             }
             + self.storage.n_rows()
     }
@@ -239,25 +490,113 @@ impl Path {
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for Claim {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
-        let [old_root, new_root] = trace.account_path.clone().map(|path| fr(path.root));
+/// One level of the account trie path, from the account leaf towards the root.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct AddressHashTrace {
+    pub direction: bool,
+    pub domain: HashDomain,
+    pub open: Fr,
+    pub close: Fr,
+    pub sibling: Fr,
+    pub is_padding_open: bool,
+    pub is_padding_close: bool,
+}
+
+impl AddressHashTrace {
+    pub fn path_type(&self) -> PathType {
+        match (self.is_padding_open, self.is_padding_close) {
+            (false, false) => PathType::Common,
+            (false, true) => PathType::ExtensionOld,
+            (true, false) => PathType::ExtensionNew,
+            (true, true) => unreachable!(),
+        }
+    }
+}
+
+impl Debug for AddressHashTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AddressHashTrace")
+            .field("direction", &u8::from(self.direction))
+            .field("domain", &self.domain)
+            .field("path_type", &self.path_type())
+            .field("open", &self.open)
+            .field("close", &self.close)
+            .field("sibling", &self.sibling)
+            .finish()
+    }
+}
+
+impl Display for AddressHashTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} {} open={:?} close={:?} sibling={:?}",
+            u8::from(self.direction),
+            self.domain,
+            self.path_type(),
+            self.open,
+            self.close,
+            self.sibling
+        )
+    }
+}
+
+impl Display for Claim {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?}: {:?} -> {:?}",
+            self.kind, self.address, self.old_root, self.new_root
+        )
+    }
+}
+
+impl Display for Proof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            writeln!(f, "{id}")?;
+        }
+        writeln!(f, "{}", self.claim)?;
+        writeln!(f, "account key {:?}", self.account_key)?;
+        for trace in self.address_hash_traces.iter().rev() {
+            writeln!(f, "  {trace}")?;
+        }
+        write!(
+            f,
+            "old path {:?} {:?}, new path {:?} {:?}",
+            self.old.key, self.old.leaf_data_hash, self.new.key, self.new.leaf_data_hash
+        )
+    }
+}
+
+impl TryFrom<(&MPTProofType, &SMTTrace)> for Claim {
+    type Error = Error;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, Error> {
+        let [old_root, new_root] = [0, 1].map(|i| fr(trace.account_path[i].root));
         let address = trace.address.0.into();
-        let kind = ClaimKind::from((proof_type, trace));
-        assert_eq!(MPTProofType::from(kind), *proof_type);
-        Self {
+        let kind = ClaimKind::try_from((proof_type, trace))?;
+        if MPTProofType::from(kind) != *proof_type {
+            return Err(Error::ProofTypeMismatch(*proof_type));
+        }
+        Ok(Self {
             new_root,
             old_root,
             address,
             kind,
-        }
+        })
     }
 }
 
-impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
-    fn from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Self {
+impl TryFrom<(&MPTProofType, &SMTTrace)> for ClaimKind {
+    type Error = Error;
+
+    fn try_from((proof_type, trace): (&MPTProofType, &SMTTrace)) -> Result<Self, Error> {
         let [account_old, account_new] = &trace.account_update;
         let state_update = &trace.state_update;
+        let mismatch = || Error::ProofTypeMismatch(*proof_type);
+        // Only the field of the proof type can change.
+        let unchanged = |equal: bool| if equal { Ok(()) } else { Err(mismatch()) };
 
         if let Some(update) = state_update {
             match update {
@@ -268,20 +607,23 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                     // will precede storage modifications for a given address, which means that the MPT circuit only
                     // needs to handle storage modifications for existing accounts, even though this is not true in the
                     // EVM, where the storage of an account can be modified during its construction.
-                    if !(account_old == account_new
-                        || (account_old.is_none() && account_new == &Some(Default::default())))
-                    {
-                        assert_eq!(account_old, account_new, "{state_update:?}");
-                    }
+                    ensure(
+                        account_old == account_new
+                            || (account_old.is_none() && account_new == &Some(Default::default())),
+                        "account changed in a storage update",
+                    )?;
                     let old_value = u256_from_hex(old.value);
                     let new_value = u256_from_hex(new.value);
 
-                    assert_eq!(old.key, new.key);
+                    ensure(
+                        old.key == new.key,
+                        "old and new storage entries have different keys",
+                    )?;
                     let key = u256_from_hex(old.key);
                     if old_value.is_zero() && new_value.is_zero() {
-                        return ClaimKind::IsEmpty(Some(key));
+                        return Ok(ClaimKind::IsEmpty(Some(key)));
                     }
-                    return ClaimKind::Storage {
+                    return Ok(ClaimKind::Storage {
                         key,
                         old_value: if old_value.is_zero() {
                             None
@@ -293,13 +635,17 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                         } else {
                             Some(new_value)
                         },
-                    };
+                    });
+                }
+                [None, Some(_)] | [Some(_), None] => {
+                    return Err(Error::InvalidTrace(
+                        "storage update has only one of the old and new entries",
+                    ))
                 }
-                [None, Some(_)] | [Some(_), None] => unreachable!(),
             }
         }
 
-        match &trace.account_update {
+        Ok(match &trace.account_update {
             [None, None] => match *proof_type {
                 MPTProofType::NonceChanged => ClaimKind::Nonce {
                     old: Some(0),
@@ -318,76 +664,92 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                     old: Some(0),
                     new: Some(0),
                 },
-                MPTProofType::StorageDoesNotExist => {
-                    ClaimKind::IsEmpty(Some(u256_from_hex(trace.state_key.unwrap())))
-                }
-                MPTProofType::PoseidonCodeHashExists => unreachable!(),
-                MPTProofType::StorageChanged => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
+                MPTProofType::StorageDoesNotExist => match trace.state_key {
+                    Some(key) => ClaimKind::IsEmpty(Some(u256_from_hex(key))),
+                    None => {
+                        return Err(Error::InvalidTrace(
+                            "storage non-existence proof without a state key",
+                        ))
+                    }
+                },
+                MPTProofType::PoseidonCodeHashExists
+                | MPTProofType::StorageChanged
+                | MPTProofType::AccountDestructed => return Err(mismatch()),
             },
             [None, Some(new)] => {
                 if !new.nonce.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::NonceChanged);
+                    unchanged(*proof_type == MPTProofType::NonceChanged)?;
                     ClaimKind::Nonce {
                         old: None,
                         new: Some(new.nonce),
                     }
                 } else if !new.balance.is_zero() {
-                    assert_eq!(*proof_type, MPTProofType::BalanceChanged);
+                    unchanged(*proof_type == MPTProofType::BalanceChanged)?;
                     ClaimKind::Balance {
                         old: None,
                         new: Some(u256_from_biguint(&new.balance)),
                     }
+                } else if !new.code_size.is_zero() {
+                    unchanged(*proof_type == MPTProofType::CodeSizeExists)?;
+                    ClaimKind::CodeSize {
+                        old: None,
+                        new: Some(new.code_size),
+                    }
                 } else {
-                    unimplemented!("nonce or balance must be first field set on empty account");
+                    // Accounts are created by setting their nonce, balance, or code size. Accounts
+                    // with code are created with a nonce of 1 (EIP-161), so their code hashes are
+                    // never the first field set.
+                    return Err(Error::UnsupportedAccountCreation {
+                        proof_type: *proof_type,
+                    });
                 }
             }
             [Some(old), Some(new)] => match *proof_type {
                 MPTProofType::NonceChanged => {
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(old.balance == new.balance)?;
+                    unchanged(old.code_size == new.code_size)?;
+                    unchanged(old.code_hash == new.code_hash)?;
+                    unchanged(old.poseidon_code_hash == new.poseidon_code_hash)?;
                     ClaimKind::Nonce {
                         old: Some(old.nonce),
                         new: Some(new.nonce),
                     }
                 }
                 MPTProofType::BalanceChanged => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(old.nonce == new.nonce)?;
+                    unchanged(old.code_size == new.code_size)?;
+                    unchanged(old.code_hash == new.code_hash)?;
+                    unchanged(old.poseidon_code_hash == new.poseidon_code_hash)?;
                     ClaimKind::Balance {
                         old: Some(u256_from_biguint(&old.balance)),
                         new: Some(u256_from_biguint(&new.balance)),
                     }
                 }
                 MPTProofType::CodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(old.nonce == new.nonce)?;
+                    unchanged(old.balance == new.balance)?;
+                    unchanged(old.code_size == new.code_size)?;
+                    unchanged(old.poseidon_code_hash == new.poseidon_code_hash)?;
                     ClaimKind::CodeHash {
                         old: Some(u256_from_biguint(&old.code_hash)),
                         new: Some(u256_from_biguint(&new.code_hash)),
                     }
                 }
                 MPTProofType::CodeSizeExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_hash, new.code_hash);
-                    assert_eq!(old.poseidon_code_hash, new.poseidon_code_hash);
+                    unchanged(old.nonce == new.nonce)?;
+                    unchanged(old.balance == new.balance)?;
+                    unchanged(old.code_hash == new.code_hash)?;
+                    unchanged(old.poseidon_code_hash == new.poseidon_code_hash)?;
                     ClaimKind::CodeSize {
                         old: Some(old.code_size),
                         new: Some(new.code_size),
                     }
                 }
                 MPTProofType::PoseidonCodeHashExists => {
-                    assert_eq!(old.nonce, new.nonce);
-                    assert_eq!(old.balance, new.balance);
-                    assert_eq!(old.code_size, new.code_size);
-                    assert_eq!(old.code_hash, new.code_hash);
+                    unchanged(old.nonce == new.nonce)?;
+                    unchanged(old.balance == new.balance)?;
+                    unchanged(old.code_size == new.code_size)?;
+                    unchanged(old.code_hash == new.code_hash)?;
                     ClaimKind::PoseidonCodeHash {
                         old: Some(big_uint_to_fr(&old.poseidon_code_hash)),
                         new: Some(big_uint_to_fr(&new.poseidon_code_hash)),
@@ -395,130 +757,87 @@ impl From<(&MPTProofType, &SMTTrace)> for ClaimKind {
                 }
                 MPTProofType::AccountDoesNotExist
                 | MPTProofType::StorageChanged
-                | MPTProofType::StorageDoesNotExist => unreachable!(),
-                MPTProofType::AccountDestructed => unimplemented!(),
+                | MPTProofType::StorageDoesNotExist
+                | MPTProofType::AccountDestructed => return Err(mismatch()),
             },
-            [Some(_old), None] => unimplemented!("SELFDESTRUCT"),
-        }
+            [Some(_old), None] => {
+                unchanged(*proof_type == MPTProofType::AccountDestructed)?;
+                ClaimKind::Destructed
+            }
+        })
     }
 }
 
+/// Panics if the trace is invalid. Use `Proof::try_from_trace` for traces that aren't trusted.
 impl From<(MPTProofType, SMTTrace)> for Proof {
-    fn from((proof, trace): (MPTProofType, SMTTrace)) -> Self {
-        let claim = Claim::from((&proof, &trace));
-
-        let storage = StorageProof::from(&trace);
-
-        let key = account_key(claim.address);
-        assert_eq!(key, fr(trace.account_key));
-
-        let account_trie_rows = TrieRows::new(
-            fr(trace.account_key),
-            &trace.account_path[0].path,
-            &trace.account_path[1].path,
-            trace.account_path[0].leaf,
-            trace.account_path[1].leaf,
-        );
-
-        let leafs = trace.account_path.clone().map(get_leaf);
-        let [open_hash_traces, close_hash_traces] =
-            trace.account_path.clone().map(|path| path.path);
-        let leaf_hashes = trace.account_path.clone().map(leaf_hash);
-        let address_hash_traces =
-            get_internal_hash_traces(key, leaf_hashes, &open_hash_traces, &close_hash_traces);
-        check_hash_traces_new(&address_hash_traces);
-
-        let [old_account, new_account] = trace.account_update;
-        let old_account_hash_traces = match old_account.clone() {
-            None => empty_account_hash_traces(leafs[0]),
-            Some(account) => account_hash_traces(claim.address, account, storage.old_root()),
-        };
-        let new_account_hash_traces = match new_account.clone() {
-            None => empty_account_hash_traces(leafs[1]),
-            Some(account) => account_hash_traces(claim.address, account, storage.new_root()),
-        };
-        assert_eq!(old_account_hash_traces[5][2], leaf_hashes[0]);
-        assert_eq!(new_account_hash_traces[5][2], leaf_hashes[1]);
-
-        let [old, new] = trace.account_path.map(|path| {
-            // The account_key(address) if the account exists
-            // else: path.leaf.sibling if it's a type 1 non-existence proof
-            // otherwise account_key(address) if it's a type 2 non-existence proof
-            let key = path
-                .leaf
-                .map_or_else(|| account_key(claim.address), |l| fr(l.sibling));
-
-            let leaf_data_hash = path.leaf.map(|leaf| fr(leaf.value));
-
-            Path {
-                key,
-                leaf_data_hash,
-            }
-        });
-
-        let old_account = match old_account {
-            Some(account_data) => {
-                let mut account = EthAccount::from(account_data);
-                account.storage_root = storage.old_root();
-                Some(account)
-            }
-            None => None,
-        };
-        let new_account = match new_account {
-            Some(account_data) => {
-                let mut account = EthAccount::from(account_data);
-                account.storage_root = storage.new_root();
-                Some(account)
-            }
-            None => None,
-        };
+    fn from((proof_type, trace): (MPTProofType, SMTTrace)) -> Self {
+        Self::try_from_trace(proof_type, trace).unwrap_or_else(|error| panic!("{error}"))
+    }
+}
 
-        Self {
-            claim,
-            address_hash_traces,
-            old_account_hash_traces,
-            new_account_hash_traces,
-            leafs,
-            storage,
-            old,
-            new,
-            old_account,
-            new_account,
-            account_trie_rows,
+// Checks the parts of a trace that converting it would otherwise panic on: the hashes and keys
+// must be field elements, the account fields must fit in 32 bytes, and the paths can't be longer
+// than the 256 bits of a key.
+fn check_trace_encoding(trace: &SMTTrace) -> Result<(), Error> {
+    let paths = trace
+        .account_path
+        .iter()
+        .chain(trace.state_path.iter().flatten());
+    let mut hashes = vec![trace.account_key];
+    hashes.extend(trace.state_key);
+    hashes.extend(trace.common_state_root);
+    for path in paths {
+        ensure(path.path.len() <= 256, "path is longer than 256 nodes")?;
+        hashes.push(path.root);
+        for node in path.path.iter().chain(&path.leaf) {
+            hashes.extend([node.value, node.sibling]);
         }
     }
+    ensure(
+        hashes
+            .iter()
+            .all(|hash| bool::from(Fr::from_bytes(&hash.0).is_some())),
+        "hash or key is not a field element",
+    )?;
+    ensure(
+        trace.account_update.iter().flatten().all(|account| {
+            [
+                &account.balance,
+                &account.code_hash,
+                &account.poseidon_code_hash,
+            ]
+            .iter()
+            .all(|x| x.bits() <= 256)
+        }),
+        "account balance or code hash is wider than 32 bytes",
+    )
 }
 
 // This should be an optional
-fn get_leaf(path: SMTPath) -> Option<LeafNode> {
+fn get_leaf(path: &SMTPath) -> Option<LeafNode> {
     path.leaf.map(|leaf| LeafNode {
         key: fr(leaf.sibling),
         value_hash: fr(leaf.value),
     })
 }
 
-fn leaf_hash(path: SMTPath) -> Fr {
-    if let Some(leaf) = path.leaf {
-        domain_hash(fr(leaf.sibling), fr(leaf.value), HashDomain::Leaf)
-    } else {
-        Fr::zero()
-    }
+fn leaf_hash(leaf: Option<LeafNode>) -> Fr {
+    leaf.map_or_else(Fr::zero, |leaf| {
+        domain_hash(leaf.key, leaf.value_hash, HashDomain::Leaf)
+    })
 }
 
-fn account_hash_traces(address: Address, account: AccountData, storage_root: Fr) -> [[Fr; 3]; 6] {
+fn account_hash_traces(account_key: Fr, account: &AccountData, storage_root: Fr) -> [[Fr; 3]; 6] {
     let (codehash_hi, codehash_lo) = hi_lo(account.code_hash);
     let h1 = domain_hash(codehash_hi, codehash_lo, HashDomain::Pair);
     let h2 = domain_hash(storage_root, h1, HashDomain::AccountFields);
 
-    let nonce_and_codesize =
-        Fr::from(account.nonce) + Fr::from(account.code_size) * Fr::from(1 << 32).square();
+    let nonce_and_codesize = pack_nonce_and_code_size(account.nonce, account.code_size);
     let balance = big_uint_to_fr(&account.balance);
     let h3 = domain_hash(nonce_and_codesize, balance, HashDomain::AccountFields);
 
     let h4 = domain_hash(h3, h2, HashDomain::AccountFields);
 
-    let account_key = account_key(address);
-
     let poseidon_codehash = big_uint_to_fr(&account.poseidon_code_hash);
     let account_hash = domain_hash(h4, poseidon_codehash, HashDomain::AccountFields);
 
@@ -541,8 +860,11 @@ fn get_internal_hash_traces(
     leaf_hashes: [Fr; 2],
     open_hash_traces: &[SMTNode],
     close_hash_traces: &[SMTNode],
-) -> Vec<(bool, HashDomain, Fr, Fr, Fr, bool, bool)> {
-    let mut address_hash_traces = vec![];
+) -> Result<Vec<AddressHashTrace>, Error> {
+    let mut address_hash_traces = Vec::with_capacity(std::cmp::max(
+        open_hash_traces.len(),
+        close_hash_traces.len(),
+    ));
     for (i, e) in open_hash_traces
         .iter()
         .zip_longest(close_hash_traces.iter())
@@ -551,78 +873,86 @@ fn get_internal_hash_traces(
         let direction = key.bit(i);
         address_hash_traces.push(match e {
             EitherOrBoth::Both(open, close) => {
-                assert_eq!(open.sibling, close.sibling);
-                let open_domain = HashDomain::try_from(open.node_type).unwrap();
-                let close_domain = HashDomain::try_from(close.node_type).unwrap();
+                ensure(
+                    open.sibling == close.sibling,
+                    "siblings of common nodes differ",
+                )?;
+                let open_domain = node_domain(open)?;
+                let close_domain = node_domain(close)?;
+                let is_last_common_node =
+                    i + 1 == std::cmp::min(open_hash_traces.len(), close_hash_traces.len());
 
                 let domain = if open_domain != close_domain {
                     // This can only happen when inserting or deleting a node.
-                    assert!(open_hash_traces.len() != close_hash_traces.len());
-                    assert!(
-                        i == std::cmp::min(open_hash_traces.len(), close_hash_traces.len()) - 1
-                    );
+                    ensure(
+                        open_hash_traces.len() != close_hash_traces.len() && is_last_common_node,
+                        "branch type changed above the inserted or deleted leaf",
+                    )?;
 
                     if i == open_hash_traces.len() - 1 {
                         // Inserting a leaf, so open is before insertion, close is after insertion.
-                        check_domain_consistency(open_domain, close_domain, direction);
+                        check_domain_consistency(open_domain, close_domain, direction)?;
                         open_domain
                     } else {
                         // Deleting a leaf, so open is after insertion, close is before insertion.
-                        check_domain_consistency(close_domain, open_domain, direction);
+                        check_domain_consistency(close_domain, open_domain, direction)?;
                         close_domain
                     }
                 } else {
                     open_domain
                 };
+                ensure_insertion_domain(
+                    domain,
+                    open_hash_traces.len() != close_hash_traces.len() && is_last_common_node,
+                )?;
 
-                (
+                AddressHashTrace {
                     direction,
                     domain,
-                    fr(open.value),
-                    fr(close.value),
-                    fr(open.sibling),
-                    false,
-                    false,
-                )
+                    open: fr(open.value),
+                    close: fr(close.value),
+                    sibling: fr(open.sibling),
+                    is_padding_open: false,
+                    is_padding_close: false,
+                }
             }
-            EitherOrBoth::Left(open) => (
+            EitherOrBoth::Left(open) => AddressHashTrace {
                 direction,
-                HashDomain::try_from(open.node_type).unwrap(),
-                fr(open.value),
-                leaf_hashes[1],
-                fr(open.sibling),
-                false,
-                true,
-            ),
-            EitherOrBoth::Right(close) => (
+                domain: node_domain(open)?,
+                open: fr(open.value),
+                close: leaf_hashes[1],
+                sibling: fr(open.sibling),
+                is_padding_open: false,
+                is_padding_close: true,
+            },
+            EitherOrBoth::Right(close) => AddressHashTrace {
                 direction,
-                HashDomain::try_from(close.node_type).unwrap(),
-                leaf_hashes[0],
-                fr(close.value),
-                fr(close.sibling),
-                true,
-                false,
-            ),
+                domain: node_domain(close)?,
+                open: leaf_hashes[0],
+                close: fr(close.value),
+                sibling: fr(close.sibling),
+                is_padding_open: true,
+                is_padding_close: false,
+            },
         });
     }
     address_hash_traces.reverse();
-    address_hash_traces
+    Ok(address_hash_traces)
 }
 
-fn empty_account_hash_traces(leaf: Option<LeafNode>) -> [[Fr; 3]; 6] {
+fn empty_account_hash_traces(leaf: Option<LeafNode>, leaf_hash: Fr) -> [[Fr; 3]; 6] {
     let mut account_hash_traces = [[Fr::zero(); 3]; 6];
     if let Some(l) = leaf {
-        account_hash_traces[5] = [
-            l.key,
-            l.value_hash,
-            domain_hash(l.key, l.value_hash, HashDomain::Leaf),
-        ];
+        account_hash_traces[5] = [l.key, l.value_hash, leaf_hash];
     }
     account_hash_traces
 }
 
 impl Proof {
-    pub fn old_account_leaf_hashes(&self) -> Option<Vec<Fr>> {
+    // The account leaf hashes and siblings are returned as fixed size arrays, padded with 0s,
+    // since there are at most 4 account leaf rows. Only the first n entries are used, where n
+    // is the number of account leaf rows for the proof type.
+    pub fn old_account_leaf_hashes(&self) -> Option<[Fr; 4]> {
         // TODO: make old_account_hash_traces optional
         let old_account_hash_traces = self.old_account_hash_traces;
         match self.claim.kind {
@@ -631,42 +961,53 @@ impl Proof {
                 let old_h4 = old_account_hash_traces[4][0];
                 let old_h3 = old_account_hash_traces[3][0];
                 let old_nonce_and_codesize = old_account_hash_traces[2][0];
-                vec![old_account_hash, old_h4, old_h3, old_nonce_and_codesize]
+                [old_account_hash, old_h4, old_h3, old_nonce_and_codesize]
             }),
             ClaimKind::Balance { old, .. } => old.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_h4 = old_account_hash_traces[4][0];
                 let old_h3 = old_account_hash_traces[3][0];
                 let old_balance = old_account_hash_traces[2][1];
-                vec![old_account_hash, old_h4, old_h3, old_balance]
+                [old_account_hash, old_h4, old_h3, old_balance]
             }),
             ClaimKind::PoseidonCodeHash { old, .. } => old.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_poseidon_code_hash = old_account_hash_traces[4][1];
-                vec![old_account_hash, old_poseidon_code_hash]
+                [
+                    old_account_hash,
+                    old_poseidon_code_hash,
+                    Fr::zero(),
+                    Fr::zero(),
+                ]
             }),
             ClaimKind::CodeHash { old, .. } => old.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_h4 = old_account_hash_traces[4][0];
                 let old_h2 = old_account_hash_traces[1][2];
                 let old_h1 = old_account_hash_traces[0][2];
-                vec![old_account_hash, old_h4, old_h2, old_h1]
+                [old_account_hash, old_h4, old_h2, old_h1]
             }),
             ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => self.old_account.map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
                 let old_h4 = old_account_hash_traces[4][0];
                 let old_h2 = old_account_hash_traces[1][2];
                 let old_storage_root = old_account_hash_traces[1][0];
-                vec![old_account_hash, old_h4, old_h2, old_storage_root]
+                [old_account_hash, old_h4, old_h2, old_storage_root]
             }),
             ClaimKind::IsEmpty(None) => self.leafs[0].map(|_| {
                 let old_account_hash = old_account_hash_traces[5][1];
-                vec![old_account_hash]
+                [old_account_hash, Fr::zero(), Fr::zero(), Fr::zero()]
             }),
+            ClaimKind::Destructed => Some([
+                old_account_hash_traces[5][1],
+                Fr::zero(),
+                Fr::zero(),
+                Fr::zero(),
+            ]),
         }
     }
 
-    pub fn new_account_leaf_hashes(&self) -> Option<Vec<Fr>> {
+    pub fn new_account_leaf_hashes(&self) -> Option<[Fr; 4]> {
         let new_account_hash_traces = self.new_account_hash_traces;
         match self.claim.kind {
             ClaimKind::Nonce { new, .. } | ClaimKind::CodeSize { new, .. } => new.map(|_| {
@@ -674,43 +1015,51 @@ impl Proof {
                 let new_h4 = new_account_hash_traces[4][0];
                 let new_h3 = new_account_hash_traces[3][0];
                 let new_nonce_and_codesize = new_account_hash_traces[2][0];
-                vec![new_account_hash, new_h4, new_h3, new_nonce_and_codesize]
+                [new_account_hash, new_h4, new_h3, new_nonce_and_codesize]
             }),
             ClaimKind::Balance { new, .. } => new.map(|_| {
                 let new_account_hash = new_account_hash_traces[5][1];
                 let new_h4 = new_account_hash_traces[4][0];
                 let new_h3 = new_account_hash_traces[3][0];
                 let new_balance = new_account_hash_traces[2][1];
-                vec![new_account_hash, new_h4, new_h3, new_balance]
+                [new_account_hash, new_h4, new_h3, new_balance]
             }),
             ClaimKind::PoseidonCodeHash { new, .. } => new.map(|_| {
                 let new_account_hash = new_account_hash_traces[5][1];
                 let new_poseidon_code_hash = new_account_hash_traces[4][1];
-                vec![new_account_hash, new_poseidon_code_hash]
+                [
+                    new_account_hash,
+                    new_poseidon_code_hash,
+                    Fr::zero(),
+                    Fr::zero(),
+                ]
             }),
             ClaimKind::CodeHash { new, .. } => new.map(|_| {
                 let new_account_hash = new_account_hash_traces[5][1];
                 let new_h4 = new_account_hash_traces[4][0];
                 let new_h2 = new_account_hash_traces[1][2];
                 let new_h1 = new_account_hash_traces[0][2];
-                vec![new_account_hash, new_h4, new_h2, new_h1]
+                [new_account_hash, new_h4, new_h2, new_h1]
             }),
             ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => {
                 let new_account_hash = new_account_hash_traces[5][1];
                 let new_h4 = new_account_hash_traces[4][0];
                 let new_h2 = new_account_hash_traces[1][2];
                 let new_storage_root = new_account_hash_traces[1][0];
-                Some(vec![new_account_hash, new_h4, new_h2, new_storage_root])
+                Some([new_account_hash, new_h4, new_h2, new_storage_root])
             }
             ClaimKind::IsEmpty(None) => self.leafs[1].map(|_| {
                 let new_account_hash = new_account_hash_traces[5][1];
-                vec![new_account_hash]
+                [new_account_hash, Fr::zero(), Fr::zero(), Fr::zero()]
             }),
+            // The account leaf is deleted, so the new hash of the AccountLeaf0 row is that of
+            // the node that replaces it, which is what the account trie rows end in.
+            ClaimKind::Destructed => None,
         }
     }
 
-    pub fn account_leaf_siblings(&self) -> Vec<Fr> {
-        let account_key = account_key(self.claim.address);
+    pub fn account_leaf_siblings(&self) -> [Fr; 4] {
+        let account_key = self.account_key;
         match self.claim.kind {
             ClaimKind::Nonce { old, new } | ClaimKind::CodeSize { old, new } => {
                 let account_hash_traces = match (old, new) {
@@ -722,7 +1071,7 @@ impl Proof {
                 let h2 = account_hash_traces[3][1];
                 let poseidon_codehash = account_hash_traces[4][1];
 
-                vec![account_key, poseidon_codehash, h2, balance]
+                [account_key, poseidon_codehash, h2, balance]
             }
             ClaimKind::Balance { old, new } => {
                 let account_hash_traces = match (old, new) {
@@ -734,7 +1083,7 @@ impl Proof {
                 let h2 = account_hash_traces[3][1];
                 let poseidon_codehash = account_hash_traces[4][1];
 
-                vec![account_key, poseidon_codehash, h2, nonce_and_codesize]
+                [account_key, poseidon_codehash, h2, nonce_and_codesize]
             }
             ClaimKind::PoseidonCodeHash { old, new } => {
                 let account_hash_traces = match (old, new) {
@@ -744,7 +1093,7 @@ impl Proof {
                 };
                 let h4 = account_hash_traces[4][0];
 
-                vec![account_key, h4]
+                [account_key, h4, Fr::zero(), Fr::zero()]
             }
             ClaimKind::CodeHash { old, new } => {
                 let account_hash_traces = match (old, new) {
@@ -755,7 +1104,7 @@ impl Proof {
                 let poseidon_codehash = account_hash_traces[4][1];
                 let h3 = account_hash_traces[3][0];
                 let storage_root = account_hash_traces[1][0];
-                vec![account_key, poseidon_codehash, h3, storage_root]
+                [account_key, poseidon_codehash, h3, storage_root]
             }
             ClaimKind::Storage { .. } | ClaimKind::IsEmpty(Some(_)) => {
                 assert_eq!(
@@ -774,9 +1123,10 @@ impl Proof {
                 let poseidon_codehash = self.old_account_hash_traces[4][1];
                 let h3 = self.old_account_hash_traces[3][0];
                 let keccak_codehash_hash = self.old_account_hash_traces[1][1];
-                vec![account_key, poseidon_codehash, h3, keccak_codehash_hash]
+                [account_key, poseidon_codehash, h3, keccak_codehash_hash]
             }
-            ClaimKind::IsEmpty(None) => vec![],
+            ClaimKind::IsEmpty(None) => [Fr::zero(), Fr::zero(), Fr::zero(), Fr::zero()],
+            ClaimKind::Destructed => [account_key, Fr::zero(), Fr::zero(), Fr::zero()],
         }
     }
 
@@ -787,27 +1137,26 @@ impl Proof {
         self.storage.check();
 
         // poseidon hashes are correct
-        check_hash_traces_new(&self.address_hash_traces);
+        check_hash_traces_new(&self.address_hash_traces).unwrap();
 
         // directions match account key.
-        let account_key = account_key(self.claim.address);
-        for (i, (direction, _, _, _, _, _, _)) in self.address_hash_traces.iter().enumerate() {
+        let account_key = self.account_key;
+        for (i, trace) in self.address_hash_traces.iter().enumerate() {
             assert_eq!(
-                *direction,
+                trace.direction,
                 account_key.bit(self.address_hash_traces.len() - i - 1)
             );
         }
 
         // old and new roots are correct
-        if let Some((
+        if let Some(AddressHashTrace {
             direction,
             domain,
             open,
             close,
             sibling,
-            _is_padding_open,
-            _is_padding_close,
-        )) = self.address_hash_traces.last()
+            ..
+        }) = self.address_hash_traces.last()
         {
             if *direction {
                 assert_eq!(domain_hash(*sibling, *open, *domain), self.claim.old_root);
@@ -825,12 +1174,12 @@ impl Proof {
 
         assert_eq!(
             self.old_account_hash_traces[5][2],
-            self.address_hash_traces.first().unwrap().2
+            self.address_hash_traces.first().unwrap().open
         );
 
         assert_eq!(
             self.new_account_hash_traces[5][2],
-            self.address_hash_traces.first().unwrap().3
+            self.address_hash_traces.first().unwrap().close
         );
         if let Some(old_leaf) = self.leafs[0] {
             assert_eq!(
@@ -838,7 +1187,7 @@ impl Proof {
                 self.old_account_hash_traces[5][2],
             );
         } else {
-            assert_eq!(self.address_hash_traces.first().unwrap().2, Fr::zero())
+            assert_eq!(self.address_hash_traces.first().unwrap().open, Fr::zero())
         }
         if let Some(new_leaf) = self.leafs[1] {
             assert_eq!(
@@ -846,7 +1195,7 @@ impl Proof {
                 self.new_account_hash_traces[5][2],
             );
         } else {
-            assert_eq!(self.address_hash_traces.first().unwrap().3, Fr::zero())
+            assert_eq!(self.address_hash_traces.first().unwrap().close, Fr::zero())
         }
 
         // // storage poseidon hashes are correct
@@ -879,85 +1228,94 @@ impl Proof {
     }
 }
 
-fn check_hash_traces_new(traces: &[(bool, HashDomain, Fr, Fr, Fr, bool, bool)]) {
+fn check_hash_traces_new(traces: &[AddressHashTrace]) -> Result<(), Error> {
     let mut previous_path_type: Option<PathType> = None;
 
     let current_hash_traces = traces.iter();
     let mut next_hash_traces = traces.iter();
     next_hash_traces.next();
     for (
-        (direction, domain, open, close, sibling, is_padding_open, is_padding_close),
-        (_, _, next_open, next_close, _, _, _),
+        trace @ AddressHashTrace {
+            direction,
+            domain,
+            open,
+            close,
+            sibling,
+            ..
+        },
+        AddressHashTrace {
+            open: next_open,
+            close: next_close,
+            ..
+        },
     ) in current_hash_traces.zip(next_hash_traces)
     {
-        let path_type = match (is_padding_open, is_padding_close) {
-            (false, false) => PathType::Common,
-            (false, true) => PathType::ExtensionOld,
-            (true, false) => PathType::ExtensionNew,
-            (true, true) => unreachable!(),
-        };
+        let path_type = trace.path_type();
 
         match path_type {
             PathType::Start => unreachable!(),
             PathType::Common => {
-                let [open_domain, close_domain] =
-                    if previous_path_type == Some(PathType::ExtensionOld) {
-                        unimplemented!("account leaf deletion");
-                    } else if previous_path_type == Some(PathType::ExtensionNew) {
-                        match *domain {
-                            HashDomain::Branch0 => [
-                                HashDomain::Branch0,
-                                if *direction {
-                                    HashDomain::Branch1
-                                } else {
-                                    HashDomain::Branch2
-                                },
-                            ],
-                            HashDomain::Branch1 => [HashDomain::Branch1, HashDomain::Branch3],
-                            HashDomain::Branch2 => [HashDomain::Branch2, HashDomain::Branch3],
-                            HashDomain::Branch3 => {
-                                unreachable!("both siblings already present")
-                            }
-                            _ => unreachable!(),
-                        }
-                    } else {
-                        [*domain, *domain]
-                    };
+                // The domain of the lowest common node changes when a leaf is inserted below it
+                // or deleted from below it, and the trace has the domain without the leaf.
+                let [open_domain, close_domain] = match previous_path_type {
+                    Some(PathType::ExtensionNew) => [*domain, next_domain(*domain, *direction)],
+                    Some(PathType::ExtensionOld) => [next_domain(*domain, *direction), *domain],
+                    _ => [*domain, *domain],
+                };
 
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, open_domain), *next_open);
-                    assert_eq!(domain_hash(*sibling, *close, close_domain), *next_close);
+                let hashes = if *direction {
+                    [
+                        domain_hash(*sibling, *open, open_domain),
+                        domain_hash(*sibling, *close, close_domain),
+                    ]
                 } else {
-                    assert_eq!(domain_hash(*open, *sibling, open_domain), *next_open);
-                    assert_eq!(domain_hash(*close, *sibling, close_domain), *next_close);
-                }
+                    [
+                        domain_hash(*open, *sibling, open_domain),
+                        domain_hash(*close, *sibling, close_domain),
+                    ]
+                };
+                ensure(
+                    hashes == [*next_open, *next_close],
+                    "account path node doesn't hash to its parent",
+                )?;
             }
             PathType::ExtensionOld => {
-                assert!(
+                ensure(
                     previous_path_type.is_none()
-                        || previous_path_type == Some(PathType::ExtensionOld)
-                );
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *open, *domain), *next_open);
+                        || previous_path_type == Some(PathType::ExtensionOld),
+                    "old and new extensions of an account path are mixed",
+                )?;
+                let hash = if *direction {
+                    domain_hash(*sibling, *open, *domain)
                 } else {
-                    assert_eq!(domain_hash(*open, *sibling, *domain), *next_open);
-                }
+                    domain_hash(*open, *sibling, *domain)
+                };
+                ensure(
+                    hash == *next_open,
+                    "account path node doesn't hash to its parent",
+                )?;
             }
             PathType::ExtensionNew => {
-                assert!(
+                ensure(
                     previous_path_type.is_none()
-                        || previous_path_type == Some(PathType::ExtensionNew)
-                );
-                if *direction {
-                    assert_eq!(domain_hash(*sibling, *close, *domain), *next_close);
+                        || previous_path_type == Some(PathType::ExtensionNew),
+                    "old and new extensions of an account path are mixed",
+                )?;
+                let hash = if *direction {
+                    domain_hash(*sibling, *close, *domain)
                 } else {
-                    assert_eq!(domain_hash(*close, *sibling, *domain), *next_close);
-                }
+                    domain_hash(*close, *sibling, *domain)
+                };
+                ensure(
+                    hash == *next_close,
+                    "account path node doesn't hash to its parent",
+                )?;
             }
         }
 
         previous_path_type = Some(path_type);
     }
+    Ok(())
 }
 
 fn fr(x: HexBytes<32>) -> Fr {
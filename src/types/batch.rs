@@ -0,0 +1,52 @@
+use crate::{types::Proof, Error, MptCircuitConfig};
+use std::ops::Deref;
+
+/// Mpt updates that are assigned together, in the order in which they are applied to the trie.
+/// Each update starts from the root that the update before it ends at. Derefs to `[Proof]`, so a
+/// batch can be passed to `MptCircuitConfig::assign` directly.
+#[derive(Clone, Debug, Default)]
+pub struct ProofBatch {
+    proofs: Vec<Proof>,
+}
+
+impl ProofBatch {
+    /// Returns an error if the old root of a proof isn't the new root of the proof before it.
+    pub fn new(proofs: Vec<Proof>) -> Result<Self, Error> {
+        for (index, (previous, proof)) in proofs.iter().zip(proofs.iter().skip(1)).enumerate() {
+            if proof.claim.old_root != previous.claim.new_root {
+                return Err(Error::UnchainedRoots {
+                    index: index + 1,
+                    id: proof.id.clone(),
+                    old_root: proof.claim.old_root,
+                    previous_new_root: previous.claim.new_root,
+                });
+            }
+        }
+        Ok(Self { proofs })
+    }
+
+    /// The number of rows the mpt circuit needs to assign the batch.
+    pub fn n_rows_required(&self) -> usize {
+        MptCircuitConfig::n_rows_required(&self.proofs)
+    }
+
+    pub fn into_proofs(self) -> Vec<Proof> {
+        self.proofs
+    }
+}
+
+impl Deref for ProofBatch {
+    type Target = [Proof];
+
+    fn deref(&self) -> &[Proof] {
+        &self.proofs
+    }
+}
+
+impl TryFrom<Vec<Proof>> for ProofBatch {
+    type Error = Error;
+
+    fn try_from(proofs: Vec<Proof>) -> Result<Self, Error> {
+        Self::new(proofs)
+    }
+}
@@ -3,7 +3,8 @@ use crate::types::{Bit, PathType};
 use crate::{
     serde::{SMTNode, SMTTrace, StateData},
     types::{trie::TrieRows, HashDomain},
-    util::{domain_hash, fr, storage_key_hash, u256_from_hex, u256_hi_lo},
+    util::{domain_hash, ensure, fr, storage_key_hash, u256_from_hex, u256_hi_lo},
+    Error,
 };
 use ethers_core::{k256::elliptic_curve::PrimeField, types::U256};
 use halo2_proofs::halo2curves::bn256::Fr;
@@ -62,9 +63,9 @@ impl StorageProof {
         }
     }
 
-    pub fn poseidon_lookups(&self) -> Vec<(Fr, Fr, HashDomain, Fr)> {
+    pub fn poseidon_lookups(&self, lookups: &mut impl Extend<(Fr, Fr, HashDomain, Fr)>) {
         match self {
-            Self::Root(_) => vec![],
+            Self::Root(_) => (),
             Self::Update {
                 storage_key,
                 key,
@@ -74,16 +75,15 @@ impl StorageProof {
                 ..
             } => {
                 let (key_high, key_low) = u256_hi_lo(storage_key);
-                let mut lookups = vec![(
+                lookups.extend([(
                     Fr::from_u128(key_high),
                     Fr::from_u128(key_low),
                     HashDomain::Pair,
                     *key,
-                )];
-                lookups.extend(trie_rows.poseidon_lookups());
-                lookups.extend(old_leaf.poseidon_lookups());
-                lookups.extend(new_leaf.poseidon_lookups());
-                lookups
+                )]);
+                trie_rows.poseidon_lookups(lookups);
+                old_leaf.poseidon_lookups(lookups);
+                new_leaf.poseidon_lookups(lookups);
             }
         }
     }
@@ -97,11 +97,11 @@ impl StorageProof {
         }
     }
 
-    pub fn key_bit_lookups(&self) -> Vec<(Fr, usize, bool)> {
+    pub fn key_bit_lookups(&self, lookups: &mut impl Extend<(Fr, usize, bool)>) {
         match self {
-            Self::Root(_) => vec![],
+            Self::Root(_) => (),
             Self::Update { trie_rows, .. } => {
-                trie_rows.key_bit_lookups(self.key(), self.other_key())
+                trie_rows.key_bit_lookups(self.key(), self.other_key(), lookups)
             }
         }
     }
@@ -174,24 +174,27 @@ impl StorageProof {
 }
 
 impl StorageLeaf {
-    fn new(mpt_key: Fr, node: &Option<SMTNode>, data: &StateData) -> Self {
+    fn new(mpt_key: Fr, node: &Option<SMTNode>, data: &StateData) -> Result<Self, Error> {
         let value = u256_from_hex(data.value);
         match (node, value.is_zero()) {
-            (None, true) => Self::Empty { mpt_key },
+            (None, true) => Ok(Self::Empty { mpt_key }),
             (Some(node), true) => {
-                assert_eq!(mpt_key, storage_key_hash(u256_from_hex(data.key)));
-                Self::Leaf {
+                ensure(
+                    mpt_key == storage_key_hash(u256_from_hex(data.key)),
+                    "state key isn't the hash of the storage key",
+                )?;
+                Ok(Self::Leaf {
                     mpt_key: fr(node.sibling),
                     value_hash: fr(node.value),
-                }
+                })
             }
-            (Some(_), false) => Self::Entry {
+            (Some(_), false) => Ok(Self::Entry {
                 storage_key: u256_from_hex(data.key),
                 value,
-            },
-            (None, false) => {
-                unreachable!();
-            }
+            }),
+            (None, false) => Err(Error::InvalidTrace(
+                "nonzero storage value without a storage leaf",
+            )),
         }
     }
 
@@ -251,14 +254,14 @@ impl StorageLeaf {
         }
     }
 
-    fn poseidon_lookups(&self) -> Vec<(Fr, Fr, HashDomain, Fr)> {
+    fn poseidon_lookups(&self, lookups: &mut impl Extend<(Fr, Fr, HashDomain, Fr)>) {
         match self {
-            Self::Empty { .. } => vec![],
+            Self::Empty { .. } => (),
             Self::Leaf { value_hash, .. } => {
-                vec![(self.key(), *value_hash, HashDomain::Leaf, self.hash())]
+                lookups.extend([(self.key(), *value_hash, HashDomain::Leaf, self.hash())]);
             }
             Self::Entry { .. } => {
-                vec![
+                lookups.extend([
                     (
                         self.value_high(),
                         self.value_low(),
@@ -266,34 +269,45 @@ impl StorageLeaf {
                         self.value_hash(),
                     ),
                     (self.key(), self.value_hash(), HashDomain::Leaf, self.hash()),
-                ]
+                ]);
             }
         }
     }
 }
 
-impl From<&SMTTrace> for StorageProof {
-    fn from(trace: &SMTTrace) -> Self {
+impl TryFrom<&SMTTrace> for StorageProof {
+    type Error = Error;
+
+    fn try_from(trace: &SMTTrace) -> Result<Self, Error> {
         if let Some(root) = trace.common_state_root {
-            return Self::Root(fr(root));
+            return Ok(Self::Root(fr(root)));
         }
-        let key = fr(trace.state_key.unwrap());
-        let [old_path, new_path] = &trace.state_path;
-        let old_leaf = old_path.as_ref().unwrap().leaf;
-        let new_leaf = new_path.as_ref().unwrap().leaf;
+        let (
+            Some(state_key),
+            [Some(old_path), Some(new_path)],
+            Some([Some(old_entry), Some(new_entry)]),
+        ) = (trace.state_key, &trace.state_path, trace.state_update)
+        else {
+            return Err(Error::InvalidTrace(
+                "storage update without a state key, paths, or entries",
+            ));
+        };
+        let key = fr(state_key);
         let trie_rows = TrieRows::new(
             key,
-            &old_path.as_ref().unwrap().path,
-            &new_path.as_ref().unwrap().path,
-            old_leaf,
-            new_leaf,
-        );
+            &old_path.path,
+            &new_path.path,
+            old_path.leaf,
+            new_path.leaf,
+        )?;
 
-        let [old_entry, new_entry] = trace.state_update.unwrap().map(Option::unwrap);
-        assert_eq!(old_entry.key, new_entry.key);
+        ensure(
+            old_entry.key == new_entry.key,
+            "old and new storage entries have different keys",
+        )?;
         let storage_key = u256_from_hex(old_entry.key);
-        let old_leaf = StorageLeaf::new(key, &old_leaf, &old_entry);
-        let new_leaf = StorageLeaf::new(key, &new_leaf, &new_entry);
+        let old_leaf = StorageLeaf::new(key, &old_path.leaf, &old_entry)?;
+        let new_leaf = StorageLeaf::new(key, &new_path.leaf, &new_entry)?;
 
         let storage_proof = Self::Update {
             storage_key,
@@ -302,14 +316,11 @@ impl From<&SMTTrace> for StorageProof {
             old_leaf,
             new_leaf,
         };
-        assert_eq!(
-            storage_proof.old_root(),
-            fr(old_path.as_ref().unwrap().root)
-        );
-        assert_eq!(
-            storage_proof.new_root(),
-            fr(new_path.as_ref().unwrap().root)
-        );
-        storage_proof
+        ensure(
+            storage_proof.old_root() == fr(old_path.root)
+                && storage_proof.new_root() == fr(new_path.root),
+            "storage path doesn't hash to its root",
+        )?;
+        Ok(storage_proof)
     }
 }
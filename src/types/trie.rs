@@ -2,7 +2,8 @@ use crate::{
     gadgets::mpt_update::PathType,
     serde::SMTNode,
     types::HashDomain,
-    util::{check_domain_consistency, domain_hash, fr, Bit},
+    util::{check_domain_consistency, domain_hash, ensure, fr, Bit},
+    Error,
 };
 use halo2_proofs::halo2curves::bn256::Fr;
 use itertools::{EitherOrBoth, Itertools};
@@ -58,13 +59,15 @@ impl TrieRow {
 }
 
 impl TrieRows {
+    /// Returns an error if the old and new paths aren't the paths of an update, insertion, or
+    /// deletion of the leaf at key.
     pub fn new(
         key: Fr,
         old_nodes: &[SMTNode],
         new_nodes: &[SMTNode],
         old_leaf: Option<SMTNode>,
         new_leaf: Option<SMTNode>,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         let old_leaf_hash = old_nodes
             .last()
             .map(|node| fr(node.value))
@@ -73,74 +76,83 @@ impl TrieRows {
             .last()
             .map(|node| fr(node.value))
             .unwrap_or_else(|| new_leaf.map(leaf_hash).unwrap_or_default());
-        Self(
-            old_nodes
-                .iter()
-                .zip_longest(new_nodes.iter())
-                .enumerate()
-                .map(|(i, pair)| {
-                    let direction = key.bit(i);
-                    match pair {
-                        EitherOrBoth::Both(old, new) => {
-                            assert_eq!(old.sibling, new.sibling);
+        let rows = old_nodes
+            .iter()
+            .zip_longest(new_nodes.iter())
+            .enumerate()
+            .map(|(i, pair)| {
+                let direction = key.bit(i);
+                Ok(match pair {
+                    EitherOrBoth::Both(old, new) => {
+                        ensure(
+                            old.sibling == new.sibling,
+                            "siblings of common nodes differ",
+                        )?;
 
-                            let old_domain = HashDomain::try_from(old.node_type).unwrap();
-                            let new_domain = HashDomain::try_from(new.node_type).unwrap();
-                            let domain = if old_domain != new_domain {
-                                // This can only happen when inserting or deleting a node.
-                                assert!(old_nodes.len() != new_nodes.len());
-                                assert!(i == std::cmp::min(old_nodes.len(), new_nodes.len()) - 1);
+                        let old_domain = node_domain(old)?;
+                        let new_domain = node_domain(new)?;
+                        let is_last_common_node =
+                            i + 1 == std::cmp::min(old_nodes.len(), new_nodes.len());
+                        let domain = if old_domain != new_domain {
+                            // This can only happen when inserting or deleting a node.
+                            ensure(
+                                old_nodes.len() != new_nodes.len() && is_last_common_node,
+                                "branch type changed above the inserted or deleted leaf",
+                            )?;
 
-                                if i == old_nodes.len() - 1 {
-                                    // Inserting a leaf, so old is before insertion, new is after insertion.
-                                    check_domain_consistency(old_domain, new_domain, direction);
-                                    old_domain
-                                } else {
-                                    // Deleting a leaf, so new is after insertion, old is before insertion.
-                                    check_domain_consistency(new_domain, old_domain, direction);
-                                    new_domain
-                                }
-                            } else {
+                            if i == old_nodes.len() - 1 {
+                                // Inserting a leaf, so old is before insertion, new is after insertion.
+                                check_domain_consistency(old_domain, new_domain, direction)?;
                                 old_domain
-                            };
-
-                            TrieRow {
-                                domain,
-                                direction,
-                                old: fr(old.value),
-                                new: fr(new.value),
-                                sibling: fr(old.sibling),
-                                path_type: PathType::Common,
+                            } else {
+                                // Deleting a leaf, so new is after insertion, old is before insertion.
+                                check_domain_consistency(new_domain, old_domain, direction)?;
+                                new_domain
                             }
-                        }
-                        EitherOrBoth::Left(old) => TrieRow {
-                            domain: HashDomain::try_from(old.node_type).unwrap(),
+                        } else {
+                            old_domain
+                        };
+                        ensure_insertion_domain(
+                            domain,
+                            old_nodes.len() != new_nodes.len() && is_last_common_node,
+                        )?;
+
+                        TrieRow {
+                            domain,
                             direction,
                             old: fr(old.value),
-                            new: new_leaf_hash,
-                            sibling: fr(old.sibling),
-                            path_type: PathType::ExtensionOld,
-                        },
-                        EitherOrBoth::Right(new) => TrieRow {
-                            domain: HashDomain::try_from(new.node_type).unwrap(),
-                            direction,
-                            old: old_leaf_hash,
                             new: fr(new.value),
-                            sibling: fr(new.sibling),
-                            path_type: PathType::ExtensionNew,
-                        },
+                            sibling: fr(old.sibling),
+                            path_type: PathType::Common,
+                        }
                     }
+                    EitherOrBoth::Left(old) => TrieRow {
+                        domain: node_domain(old)?,
+                        direction,
+                        old: fr(old.value),
+                        new: new_leaf_hash,
+                        sibling: fr(old.sibling),
+                        path_type: PathType::ExtensionOld,
+                    },
+                    EitherOrBoth::Right(new) => TrieRow {
+                        domain: node_domain(new)?,
+                        direction,
+                        old: old_leaf_hash,
+                        new: fr(new.value),
+                        sibling: fr(new.sibling),
+                        path_type: PathType::ExtensionNew,
+                    },
                 })
-                .collect(),
-        )
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self(rows))
     }
 
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
-    pub fn poseidon_lookups(&self) -> Vec<(Fr, Fr, HashDomain, Fr)> {
-        let mut lookups = vec![];
+    pub fn poseidon_lookups(&self, lookups: &mut impl Extend<(Fr, Fr, HashDomain, Fr)>) {
         for (i, row) in self.0.iter().enumerate() {
             let [[old_left, old_right], [new_left, new_right]] = if row.direction {
                 [[row.sibling, row.old], [row.sibling, row.new]]
@@ -156,55 +168,58 @@ impl TrieRows {
                     } else {
                         [row.domain, row.domain]
                     };
-                    lookups.push((
-                        old_left,
-                        old_right,
-                        old_domain,
-                        domain_hash(old_left, old_right, old_domain),
-                    ));
-                    lookups.push((
-                        new_left,
-                        new_right,
-                        new_domain,
-                        domain_hash(new_left, new_right, new_domain),
-                    ));
+                    lookups.extend([
+                        (
+                            old_left,
+                            old_right,
+                            old_domain,
+                            domain_hash(old_left, old_right, old_domain),
+                        ),
+                        (
+                            new_left,
+                            new_right,
+                            new_domain,
+                            domain_hash(new_left, new_right, new_domain),
+                        ),
+                    ]);
                 }
                 PathType::ExtensionOld => {
-                    lookups.push((
+                    lookups.extend([(
                         old_left,
                         old_right,
                         row.domain,
                         domain_hash(old_left, old_right, row.domain),
-                    ));
+                    )]);
                 }
                 PathType::ExtensionNew => {
-                    lookups.push((
+                    lookups.extend([(
                         new_left,
                         new_right,
                         row.domain,
                         domain_hash(new_left, new_right, row.domain),
-                    ));
+                    )]);
                 }
             }
         }
-        lookups
     }
 
-    pub fn key_bit_lookups(&self, key: Fr, other_key: Fr) -> Vec<(Fr, usize, bool)> {
-        let mut lookups = vec![];
+    pub fn key_bit_lookups(
+        &self,
+        key: Fr,
+        other_key: Fr,
+        lookups: &mut impl Extend<(Fr, usize, bool)>,
+    ) {
         for (i, row) in self.0.iter().enumerate() {
             match row.path_type {
                 PathType::Start => (),
                 PathType::Common => {
-                    lookups.push((key, i, row.direction));
-                    lookups.push((other_key, i, row.direction));
+                    lookups.extend([(key, i, row.direction), (other_key, i, row.direction)]);
                 }
                 PathType::ExtensionOld | PathType::ExtensionNew => {
-                    lookups.push((key, i, row.direction));
+                    lookups.extend([(key, i, row.direction)]);
                 }
             }
         }
-        lookups
     }
 
     pub fn old_root(&self, leaf_hash: impl FnOnce() -> Fr) -> Fr {
@@ -311,6 +326,23 @@ fn get_domains(
     domains
 }
 
+pub(crate) fn node_domain(node: &SMTNode) -> Result<HashDomain, Error> {
+    HashDomain::try_from(node.node_type).map_err(|_| Error::InvalidTrace("unknown node type"))
+}
+
+// The lowest common node of an insertion or deletion must have a leaf or empty child in the
+// direction of the insertion before it, so that `next_domain` is defined for its branch type.
+pub(crate) fn ensure_insertion_domain(domain: HashDomain, is_insertion: bool) -> Result<(), Error> {
+    ensure(
+        !is_insertion
+            || matches!(
+                domain,
+                HashDomain::Branch0 | HashDomain::Branch1 | HashDomain::Branch2
+            ),
+        "leaf inserted or deleted below a node without a leaf or empty child",
+    )
+}
+
 fn leaf_hash(leaf: SMTNode) -> Fr {
     domain_hash(fr(leaf.sibling), fr(leaf.value), HashDomain::Leaf)
 }
@@ -1,4 +1,4 @@
-use crate::{constraint_builder::Query, serde::HexBytes, types::HashDomain};
+use crate::{constraint_builder::Query, serde::HexBytes, types::HashDomain, Error};
 use ethers_core::types::{Address, U256};
 use halo2_proofs::{
     arithmetic::Field,
@@ -6,6 +6,7 @@ use halo2_proofs::{
 };
 use hash_circuit::hash::Hashable;
 use num_bigint::BigUint;
+use std::ops::{Add, Mul};
 
 pub(crate) fn fr(x: HexBytes<32>) -> Fr {
     Fr::from_bytes(&x.0).unwrap()
@@ -57,6 +58,14 @@ pub(crate) fn u256_hi_lo(x: &U256) -> (u128, u128) {
         (u128::from(u64_digits[1]) << 64) + u128::from(u64_digits[0]),
     )
 }
+/// Account leaves pack the nonce and code size into a single field element as
+/// nonce + code_size * 2^64. Both are at most 8 bytes, so the packed value is below 2^128.
+pub(crate) const CODE_SIZE_SHIFT: u128 = 1 << 64;
+
+pub(crate) fn pack_nonce_and_code_size(nonce: u64, code_size: u64) -> Fr {
+    Fr::from_u128(u128::from(code_size) * CODE_SIZE_SHIFT + u128::from(nonce))
+}
+
 pub(crate) fn fr_from_biguint(b: &BigUint) -> Fr {
     b.to_u64_digits()
         .iter()
@@ -66,11 +75,25 @@ pub(crate) fn fr_from_biguint(b: &BigUint) -> Fr {
         })
 }
 
-pub fn rlc(be_bytes: &[u8], randomness: Fr) -> Fr {
-    let x = be_bytes.iter().fold(Fr::zero(), |acc, byte| {
-        randomness * acc + Fr::from(u64::from(*byte))
-    });
-    x
+/// Random linear combination of `be_bytes`, where the last byte has coefficient 1. The
+/// randomness can be an `Fr` for witness values or a `Query` of the challenge for constraints.
+pub fn rlc<T>(be_bytes: &[u8], randomness: T) -> T
+where
+    T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+{
+    be_bytes.iter().fold(T::from(0), |acc, byte| {
+        randomness.clone() * acc + T::from(u64::from(*byte))
+    })
+}
+
+/// Like `rlc`, but for little endian bytes, so the first byte has coefficient 1.
+pub fn rlc_le<T>(le_bytes: &[u8], randomness: T) -> T
+where
+    T: Clone + From<u64> + Add<Output = T> + Mul<Output = T>,
+{
+    le_bytes.iter().rev().fold(T::from(0), |acc, byte| {
+        randomness.clone() * acc + T::from(u64::from(*byte))
+    })
 }
 
 pub fn u256_from_biguint(x: &BigUint) -> U256 {
@@ -89,26 +112,49 @@ pub fn storage_key_hash(key: U256) -> Fr {
 }
 
 pub fn account_key(address: Address) -> Fr {
-    let high_bytes: [u8; 16] = address.0[..16].try_into().unwrap();
-    let low_bytes: [u8; 4] = address.0[16..].try_into().unwrap();
+    domain_hash(
+        Fr::from_u128(address_high(address)),
+        Fr::from_u128(u128::from(address_low(address)) << 96),
+        HashDomain::Pair,
+    )
+}
 
-    let address_high = Fr::from_u128(u128::from_be_bytes(high_bytes));
-    let address_low = Fr::from_u128(u128::from(u32::from_be_bytes(low_bytes)) << 96);
-    domain_hash(address_high, address_low, HashDomain::Pair)
+pub fn address_high(a: Address) -> u128 {
+    let high_bytes: [u8; 16] = a.0[..16].try_into().unwrap();
+    u128::from_be_bytes(high_bytes)
+}
+
+pub fn address_low(a: Address) -> u32 {
+    let low_bytes: [u8; 4] = a.0[16..].try_into().unwrap();
+    u32::from_be_bytes(low_bytes)
 }
 
 // Sanity check that before and after branch types match the direction
-pub fn check_domain_consistency(before: HashDomain, after: HashDomain, direction: bool) {
-    if direction {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch1
-                || before == HashDomain::Branch2 && after == HashDomain::Branch3
-        );
+pub fn check_domain_consistency(
+    before: HashDomain,
+    after: HashDomain,
+    direction: bool,
+) -> Result<(), Error> {
+    let consistent = if direction {
+        before == HashDomain::Branch0 && after == HashDomain::Branch1
+            || before == HashDomain::Branch2 && after == HashDomain::Branch3
     } else {
-        assert!(
-            before == HashDomain::Branch0 && after == HashDomain::Branch2
-                || before == HashDomain::Branch1 && after == HashDomain::Branch3
-        );
+        before == HashDomain::Branch0 && after == HashDomain::Branch2
+            || before == HashDomain::Branch1 && after == HashDomain::Branch3
+    };
+    ensure(
+        consistent,
+        "branch types before and after an insertion don't match its direction",
+    )
+}
+
+/// Returns an `Error::InvalidTrace` with the message if the condition doesn't hold, for the
+/// checks of a trace while it is converted into a proof.
+pub(crate) fn ensure(condition: bool, message: &'static str) -> Result<(), Error> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Error::InvalidTrace(message))
     }
 }
 
@@ -144,12 +190,124 @@ pub fn lagrange_polynomial<F: FromUniformBytes<64> + Ord>(
         .expect("points.len() > 0")
 }
 
+/// Prints its context if the thread panics before it is dropped, to tell which mpt update an
+/// assert failing during witness generation was for.
+pub(crate) struct PanicContext(pub String);
+
+impl Drop for PanicContext {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("panicked while {}", self.0);
+        }
+    }
+}
+
+/// Buffer for items that only need to be sorted and deduplicated once all of them have been
+/// collected. Duplicates are compacted away whenever the buffer doubles in size, so peak memory
+/// is proportional to the number of distinct items instead of the number of pushes.
+pub(crate) struct DedupVec<T: Ord> {
+    items: Vec<T>,
+    compacted_len: usize,
+}
+
+// Don't bother compacting small buffers.
+const MIN_COMPACTION_LEN: usize = 1 << 12;
+
+impl<T: Ord> DedupVec<T> {
+    pub fn push(&mut self, item: T) {
+        self.items.push(item);
+        if self.items.len() >= 2 * std::cmp::max(self.compacted_len, MIN_COMPACTION_LEN) {
+            self.compact();
+        }
+    }
+
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        self.compact();
+        self.items.shrink_to_fit();
+        self.items
+    }
+
+    fn compact(&mut self) {
+        self.items.sort_unstable();
+        self.items.dedup();
+        self.compacted_len = self.items.len();
+    }
+}
+
+impl<T: Ord> Default for DedupVec<T> {
+    fn default() -> Self {
+        Self {
+            items: vec![],
+            compacted_len: 0,
+        }
+    }
+}
+
+impl<T: Ord> Extend<T> for DedupVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<T: Ord> FromIterator<T> for DedupVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut dedup_vec = Self::default();
+        dedup_vec.extend(iter);
+        dedup_vec
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_dedup_vec() {
+        let dedup_vec: DedupVec<_> = (0..10 * MIN_COMPACTION_LEN)
+            .rev()
+            .map(|i| i % 1000)
+            .collect();
+        assert!(dedup_vec.items.len() < 2 * MIN_COMPACTION_LEN);
+        assert_eq!(dedup_vec.into_sorted_vec(), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rlc() {
+        let randomness = Fr::from(0x100);
+        assert_eq!(rlc(&[1, 2, 3], randomness), Fr::from(0x010203));
+        assert_eq!(rlc_le(&[1, 2, 3], randomness), Fr::from(0x030201));
+        assert_eq!(rlc::<Fr>(&[], randomness), Fr::zero());
+
+        let randomness = Fr::from(0x1234567);
+        let be_bytes = u256_to_big_endian(&U256::MAX);
+        let le_bytes: Vec<_> = be_bytes.iter().rev().copied().collect();
+        assert_eq!(rlc(&be_bytes, randomness), rlc_le(&le_bytes, randomness));
+    }
+
     #[test]
     fn test_u256_hi_lo() {
         assert_eq!(u256_hi_lo(&U256::one()), (0, 1));
     }
+
+    #[test]
+    fn test_pack_nonce_and_code_size() {
+        let two_to_the_64th = Fr::from(u64::MAX) + Fr::one();
+        for (nonce, code_size) in [(0, 0), (u64::MAX, 0), (0, u64::MAX), (u64::MAX, u64::MAX)] {
+            let packed = pack_nonce_and_code_size(nonce, code_size);
+            assert_eq!(
+                packed,
+                Fr::from(nonce) + Fr::from(code_size) * two_to_the_64th
+            );
+            assert_eq!(
+                (packed - Fr::from(nonce)) * two_to_the_64th.invert().unwrap(),
+                Fr::from(code_size)
+            );
+        }
+        assert_eq!(
+            pack_nonce_and_code_size(u64::MAX, u64::MAX),
+            Fr::from_u128(u128::MAX)
+        );
+    }
 }